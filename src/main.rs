@@ -1,9 +1,19 @@
-use queuemsg;
+use queuemsg::QueueClient;
 
 
 #[tokio::main]
 async fn main() {
-    queuemsg::create_request("I'm an example request".to_string()).await;
+    let client = match QueueClient::new("my-storage-account-name", "STORAGE_ACCOUNT_KEY", "queue_name") {
+        Ok(client) => client,
+        Err(e) => {
+            println!("Couldn't create client: {}", e);
+            return;
+        }
+    };
+    match client.send_message("I'm an example request".to_string()).await {
+        Ok(sent) => println!("Successful Request!\nMessage Id: {}\nPop Receipt: {}", sent.message_id, sent.pop_receipt),
+        Err(e) => println!("Request failed: {}", e),
+    }
 
 
-}
\ No newline at end of file
+}
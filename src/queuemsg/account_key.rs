@@ -0,0 +1,71 @@
+use std::fmt;
+
+use crate::builder::{check_account_key_length, decode_account_key, BuildError};
+use crate::QueueError;
+
+/// the storage account key as the bytes that actually get signed with. The portal and connection strings
+/// give it to you as base64, which is what `QueueClientBuilder::account_key` and friends take, but key
+/// management systems don't always: this is for when you've got the raw bytes or hex instead, so there's no
+/// encoding it to base64 just for it to be decoded again.
+/// hand it to `SharedKeyCredential::from_account_key`.
+#[derive(Clone)]
+pub struct AccountKey {
+    bytes: Vec<u8>,
+}
+
+impl AccountKey {
+    /// the usual form, exactly as the portal shows it.
+    pub fn from_base64(account_key: &str) -> Result<AccountKey, QueueError> {
+        Ok(AccountKey {
+            bytes: decode_account_key(account_key)?,
+        })
+    }
+
+    /// the decoded key itself, 64 bytes for a real storage key.
+    pub fn from_bytes(account_key: &[u8]) -> Result<AccountKey, QueueError> {
+        check_account_key_length(account_key.len(), "raw")?;
+        Ok(AccountKey {
+            bytes: account_key.to_vec(),
+        })
+    }
+
+    /// the key as hex, upper or lower case, e.g. `0a1b2c...` (128 digits for a real storage key). Whitespace
+    /// around it is trimmed.
+    pub fn from_hex(account_key: &str) -> Result<AccountKey, QueueError> {
+        let digits = account_key.trim().as_bytes();
+        if !digits.len().is_multiple_of(2) {
+            return Err(invalid_hex(format!("odd number of digits ({})", digits.len())));
+        }
+        let mut bytes = Vec::<u8>::with_capacity(digits.len() / 2);
+        for (index, pair) in digits.chunks(2).enumerate() {
+            let high = hex_value(pair[0]).ok_or_else(|| not_a_digit(pair[0], index * 2))?;
+            let low = hex_value(pair[1]).ok_or_else(|| not_a_digit(pair[1], index * 2 + 1))?;
+            bytes.push(high << 4 | low);
+        }
+        check_account_key_length(bytes.len(), "hex")?;
+        Ok(AccountKey { bytes })
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+// same as the credential, a key must never end up in a log line
+impl fmt::Debug for AccountKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccountKey").field("bytes", &"<redacted>").finish()
+    }
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+fn not_a_digit(digit: u8, position: usize) -> QueueError {
+    invalid_hex(format!("'{}' at position {} is not a hex digit", (digit as char).escape_debug(), position))
+}
+
+fn invalid_hex(msg: String) -> QueueError {
+    BuildError::InvalidAccountKey(format!("not valid hex: {}", msg)).into()
+}
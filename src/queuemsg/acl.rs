@@ -0,0 +1,169 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
+use crate::sas::format_sas_time;
+use crate::{xml, QueueClient, QueueError, QueueSasPermissions};
+
+/// azure allows this many stored access policies on a queue
+pub const MAX_SIGNED_IDENTIFIERS: usize = 5;
+
+/// one stored access policy on a queue. A SAS made with `QueueSasBuilder::identifier(id)` gets whatever of
+/// `start`, `expiry` and `permissions` it doesn't set itself from here, and changing or removing the policy
+/// changes or revokes every SAS made with it without touching the account key.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/define-stored-access-policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredAccessPolicy {
+    /// up to 64 characters, unique on the queue
+    pub id: String,
+    pub start: Option<DateTime<Utc>>,
+    pub expiry: Option<DateTime<Utc>>,
+    pub permissions: Option<PolicyPermissions>,
+}
+
+/// the name azure's xml uses for a stored access policy
+pub type SignedIdentifier = StoredAccessPolicy;
+
+/// the permissions on a stored access policy. Unlike a SAS we're making, a policy read back from azure can
+/// have letters this crate doesn't know about (another tool set it, or azure added one), they end up in
+/// `unknown` as they came so a `get_acl` then `set_acl` doesn't quietly take them away.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolicyPermissions {
+    pub known: QueueSasPermissions,
+    /// letters other than r, a, u and p, in the order azure sent them
+    pub unknown: String,
+}
+
+impl PolicyPermissions {
+    /// the letters as azure has them, e.g. `raup`. Never fails, anything that isn't r, a, u or p goes in
+    /// `unknown`.
+    pub fn parse(permissions: &str) -> PolicyPermissions {
+        let mut parsed = PolicyPermissions::default();
+        for letter in permissions.chars() {
+            match letter {
+                'r' => parsed.known.read = true,
+                'a' => parsed.known.add = true,
+                'u' => parsed.known.update = true,
+                'p' => parsed.known.process = true,
+                other => parsed.unknown.push(other),
+            }
+        }
+        parsed
+    }
+}
+
+impl From<QueueSasPermissions> for PolicyPermissions {
+    fn from(known: QueueSasPermissions) -> Self {
+        PolicyPermissions {
+            known,
+            unknown: String::new(),
+        }
+    }
+}
+
+/// the known letters in azure's order, then the unknown ones
+impl fmt::Display for PolicyPermissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.known, self.unknown)
+    }
+}
+
+impl QueueClient {
+    /// the stored access policies on the queue, an empty vec if there aren't any.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/get-queue-acl
+    pub async fn get_acl(&self) -> Result<Vec<StoredAccessPolicy>, QueueError> {
+        let query = [("comp", "acl".to_string())];
+        let response = self.execute(reqwest::Method::GET, "", &query, None).await?;
+        parse_signed_identifiers(response.text()?)
+    }
+
+    /// replace the queue's stored access policies with `identifiers`, an empty vec removes them all.
+    /// azure takes up to 30 seconds to start using new policies, a SAS that uses one straight away can get a 403
+    /// in the meantime.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/set-queue-acl
+    pub async fn set_acl(&self, identifiers: &[StoredAccessPolicy]) -> Result<(), QueueError> {
+        let body = signed_identifiers_body(identifiers)?;
+        let query = [("comp", "acl".to_string())];
+        self.execute(reqwest::Method::PUT, "", &query, Some(body)).await?;
+        Ok(())
+    }
+}
+
+/// ```text
+/// <?xml version="1.0" encoding="utf-8"?>
+/// <SignedIdentifiers>
+///   <SignedIdentifier>
+///     <Id>unique-64-character-value</Id>
+///     <AccessPolicy>
+///       <Start>start-time</Start>
+///       <Expiry>expiry-time</Expiry>
+///       <Permission>abbreviated-permission-list</Permission>
+///     </AccessPolicy>
+///   </SignedIdentifier>
+/// </SignedIdentifiers>
+/// ```
+/// any of the access policy can be missing, and with no policies at all it's just `<SignedIdentifiers />`
+/// (or an empty body), which is an empty vec. Times come back with fractions, e.g. `2009-09-28T08:49:37.0000000Z`.
+fn parse_signed_identifiers(body: &str) -> Result<Vec<StoredAccessPolicy>, QueueError> {
+    let mut identifiers = Vec::<SignedIdentifier>::new();
+    for identifier in xml::elements(body, "SignedIdentifier") {
+        let id = xml::element(identifier, "Id")
+            .map(xml::unescape)
+            .ok_or_else(|| QueueError::InvalidResponse("SignedIdentifier is missing Id".to_string()))?;
+        identifiers.push(StoredAccessPolicy {
+            id,
+            start: optional_time(identifier, "Start")?,
+            expiry: optional_time(identifier, "Expiry")?,
+            permissions: xml::element(identifier, "Permission")
+                .map(|letters| PolicyPermissions::parse(&xml::unescape(letters))),
+        });
+    }
+    Ok(identifiers)
+}
+
+fn optional_time(identifier: &str, tag: &str) -> Result<Option<DateTime<Utc>>, QueueError> {
+    match xml::element(identifier, tag).map(str::trim).filter(|time| !time.is_empty()) {
+        Some(time) => DateTime::parse_from_rfc3339(time)
+            .map(|time| Some(time.with_timezone(&Utc)))
+            .map_err(|_| QueueError::InvalidResponse(format!("{} '{}' is not a valid time", tag, time))),
+        None => Ok(None),
+    }
+}
+
+/// the same document `get_acl` parses, with whatever's `None` left out.
+fn signed_identifiers_body(identifiers: &[StoredAccessPolicy]) -> Result<String, QueueError> {
+    if identifiers.len() > MAX_SIGNED_IDENTIFIERS {
+        return Err(QueueError::InvalidConfig(format!(
+            "a queue can have at most {} stored access policies, got {}",
+            MAX_SIGNED_IDENTIFIERS,
+            identifiers.len()
+        )));
+    }
+    let mut body = Vec::<String>::new();
+    body.push("<?xml version=\"1.0\" encoding=\"utf-8\"?>".to_string());
+    body.push("<SignedIdentifiers>".to_string());
+    for identifier in identifiers {
+        if identifier.id.is_empty() || identifier.id.chars().count() > 64 {
+            return Err(QueueError::InvalidConfig(format!(
+                "stored access policy id must be 1 to 64 characters, got '{}'",
+                identifier.id
+            )));
+        }
+        body.push("<SignedIdentifier>".to_string());
+        body.push(format!("<Id>{}</Id>", xml::escape(&identifier.id)));
+        body.push("<AccessPolicy>".to_string());
+        if let Some(start) = &identifier.start {
+            body.push(format!("<Start>{}</Start>", format_sas_time(start)));
+        }
+        if let Some(expiry) = &identifier.expiry {
+            body.push(format!("<Expiry>{}</Expiry>", format_sas_time(expiry)));
+        }
+        if let Some(permissions) = &identifier.permissions {
+            body.push(format!("<Permission>{}</Permission>", xml::escape(&permissions.to_string())));
+        }
+        body.push("</AccessPolicy>".to_string());
+        body.push("</SignedIdentifier>".to_string());
+    }
+    body.push("</SignedIdentifiers>".to_string());
+    Ok(body.join(""))
+}
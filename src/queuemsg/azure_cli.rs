@@ -0,0 +1,82 @@
+use chrono::{Local, NaiveDateTime, TimeZone, Utc};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::token::{AccessToken, TokenCache, TokenCredential, STORAGE_RESOURCE};
+use crate::QueueError;
+
+#[cfg(windows)]
+const AZ_BINARY: &str = "az.cmd";
+#[cfg(not(windows))]
+const AZ_BINARY: &str = "az";
+
+/// piggybacks on whoever is signed in with `az login`, for local development.
+/// `az` is slow (python, a second or so per call) so tokens are cached until shortly before they expire.
+#[derive(Debug, Default)]
+pub struct AzureCliCredential {
+    cache: TokenCache,
+}
+
+/// `az account get-access-token` output. `expires_on` (epoch seconds) only exists in newer versions of the cli,
+/// older ones only have `expiresOn` which is a local time with no timezone, e.g. `2024-01-22 10:01:02.000000`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CliToken {
+    access_token: String,
+    expires_on: String,
+    #[serde(rename = "expires_on")]
+    expires_on_epoch: Option<i64>,
+}
+
+impl AzureCliCredential {
+    pub fn new() -> AzureCliCredential {
+        AzureCliCredential::default()
+    }
+
+    async fn request_token(&self) -> Result<AccessToken, QueueError> {
+        // the resource for the cli doesn't want the trailing slash
+        let resource = STORAGE_RESOURCE.trim_end_matches('/');
+        let output = Command::new(AZ_BINARY)
+            .args(["account", "get-access-token", "--output", "json", "--resource", resource])
+            .output()
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => QueueError::Credential(
+                    "azure cli ('az') not found on PATH, install it or use a different credential".to_string(),
+                ),
+                _ => QueueError::Credential(format!("couldn't run azure cli: {}", e)),
+            })?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // an expired or missing login says so in stderr, usually with "az login" in the message
+            return Err(QueueError::Credential(match stderr.contains("az login") {
+                true => format!("azure cli is not logged in, run 'az login': {}", stderr.trim()),
+                false => format!("azure cli failed: {}", stderr.trim()),
+            }));
+        }
+
+        let token: CliToken = serde_json::from_slice(&output.stdout)
+            .map_err(|e| QueueError::Credential(format!("unexpected azure cli output: {}", e)))?;
+        let expires_on = match token.expires_on_epoch {
+            Some(secs) => Utc.timestamp_opt(secs, 0).single(),
+            None => NaiveDateTime::parse_from_str(&token.expires_on, "%Y-%m-%d %H:%M:%S%.f")
+                .ok()
+                .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+                .map(|local| local.with_timezone(&Utc)),
+        }
+        .ok_or_else(|| QueueError::Credential(format!("azure cli expiresOn '{}' is not a date", token.expires_on)))?;
+
+        Ok(AccessToken {
+            token: token.access_token,
+            expires_on,
+        })
+    }
+}
+
+impl TokenCredential for AzureCliCredential {
+    fn get_token(&self) -> BoxFuture<'_, Result<AccessToken, QueueError>> {
+        Box::pin(self.cache.get_or_refresh(|| self.request_token()))
+    }
+}
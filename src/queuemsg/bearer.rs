@@ -0,0 +1,65 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+
+use crate::token::{AccessToken, TokenCache, TokenCredential};
+use crate::QueueError;
+
+type TokenSource = Arc<dyn Fn() -> BoxFuture<'static, Result<(String, DateTime<Utc>), QueueError>> + Send + Sync>;
+
+/// bearer tokens from wherever you like - `azure_identity`, your own STS, a file a sidecar keeps fresh. Give it
+/// an async function that returns the token and when it expires, and it'll call it when the last token is
+/// within a few minutes of expiring. Concurrent sends with a stale token wait on one call rather than each
+/// making their own.
+/// hand it to `QueueClientBuilder::token_credential`, which also makes sure the x-ms-version is new enough for
+/// bearer auth.
+#[derive(Clone)]
+pub struct BearerTokenCredential {
+    source: TokenSource,
+    cache: Arc<TokenCache>,
+}
+
+impl BearerTokenCredential {
+    /// `source` is e.g. `|| async { Ok((fetch_token().await?, expiry)) }`
+    pub fn new<F, Fut>(source: F) -> BearerTokenCredential
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(String, DateTime<Utc>), QueueError>> + Send + 'static,
+    {
+        BearerTokenCredential {
+            source: Arc::new(move || Box::pin(source())),
+            cache: Arc::new(TokenCache::default()),
+        }
+    }
+
+    /// a token you already have and will replace yourself, e.g. one a deploy pipeline hands in. It's never
+    /// refreshed, once it expires requests fail with a 401 from azure until you build a client with a new one.
+    /// reach for `new` if there's any way to fetch fresh ones.
+    pub fn from_token(token: &str) -> BearerTokenCredential {
+        let token = token.trim().to_string();
+        BearerTokenCredential::new(move || {
+            let token = token.clone();
+            async move { Ok((token, DateTime::<Utc>::MAX_UTC)) }
+        })
+    }
+
+    async fn request_token(&self) -> Result<AccessToken, QueueError> {
+        let (token, expires_on) = (self.source)().await?;
+        Ok(AccessToken { token, expires_on })
+    }
+}
+
+impl fmt::Debug for BearerTokenCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BearerTokenCredential").field("cache", &self.cache).finish_non_exhaustive()
+    }
+}
+
+impl TokenCredential for BearerTokenCredential {
+    fn get_token(&self) -> BoxFuture<'_, Result<AccessToken, QueueError>> {
+        Box::pin(self.cache.get_or_refresh(|| self.request_token()))
+    }
+}
@@ -0,0 +1,85 @@
+//! a blocking wrapper around `QueueClient` for cli tools and scripts that don't want an async runtime.
+//! It's the same client underneath, signing and all, driven by a small single threaded tokio runtime.
+//! Don't use it from inside an async runtime, tokio panics if you block on one runtime from inside another.
+
+use std::sync::Arc;
+
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    PeekedMessage, QueueClient, QueueError, QueueMessage, ReceiveOptions, SendOptions, SentMessage, UpdateResult,
+};
+
+/// `QueueClient` with blocking methods, from `QueueClient::blocking()`. Cloning it shares the runtime.
+#[derive(Clone, Debug)]
+pub struct BlockingQueueClient {
+    client: QueueClient,
+    runtime: Arc<Runtime>,
+}
+
+impl QueueClient {
+    /// a blocking version of this client. Fails if the runtime can't be started (out of threads etc.)
+    pub fn blocking(self) -> Result<BlockingQueueClient, QueueError> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| QueueError::InvalidConfig(format!("couldn't start a runtime for the blocking client: {}", e)))?;
+        Ok(BlockingQueueClient {
+            client: self,
+            runtime: Arc::new(runtime),
+        })
+    }
+}
+
+impl BlockingQueueClient {
+    /// the async client underneath, for the odd thing that isn't wrapped here.
+    pub fn inner(&self) -> &QueueClient {
+        &self.client
+    }
+
+    /// see `QueueClient::send_message`
+    pub fn send_message(&self, message_text: String) -> Result<SentMessage, QueueError> {
+        self.runtime.block_on(self.client.send_message(message_text))
+    }
+
+    /// see `QueueClient::send_message_with_options`
+    pub fn send_message_with_options(
+        &self,
+        message_text: String,
+        options: &SendOptions,
+    ) -> Result<SentMessage, QueueError> {
+        self.runtime.block_on(self.client.send_message_with_options(message_text, options))
+    }
+
+    /// see `QueueClient::get_messages`
+    pub fn get_messages(&self, num: u32, visibility_timeout: Option<u32>) -> Result<Vec<QueueMessage>, QueueError> {
+        self.runtime.block_on(self.client.get_messages(num, visibility_timeout))
+    }
+
+    /// see `QueueClient::receive_messages`
+    pub fn receive_messages(&self, options: &ReceiveOptions) -> Result<Vec<QueueMessage>, QueueError> {
+        self.runtime.block_on(self.client.receive_messages(options))
+    }
+
+    /// see `QueueClient::peek_messages`
+    pub fn peek_messages(&self, num: u32) -> Result<Vec<PeekedMessage>, QueueError> {
+        self.runtime.block_on(self.client.peek_messages(num))
+    }
+
+    /// see `QueueClient::delete_message`
+    pub fn delete_message(&self, message_id: &str, pop_receipt: &str) -> Result<(), QueueError> {
+        self.runtime.block_on(self.client.delete_message(message_id, pop_receipt))
+    }
+
+    /// see `QueueClient::update_message`
+    pub fn update_message(
+        &self,
+        message_id: &str,
+        pop_receipt: &str,
+        visibility_timeout: u32,
+        new_text: Option<String>,
+    ) -> Result<UpdateResult, QueueError> {
+        self.runtime
+            .block_on(self.client.update_message(message_id, pop_receipt, visibility_timeout, new_text))
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::{Arc, OnceLock};
+
+use futures::future::BoxFuture;
+
+use crate::{
+    AccessToken, AzureCliCredential, BuildError, EnvironmentCredential, ManagedIdentityCredential, QueueError,
+    TokenCredential, WorkloadIdentityCredential,
+};
+
+/// tries each credential in order until one hands out a token, then sticks with that one for good so later
+/// requests don't pay for the failures again. If none of them work the error lists why each one failed.
+#[derive(Debug)]
+pub struct ChainedCredential {
+    sources: Vec<Arc<dyn TokenCredential>>,
+    selected: OnceLock<usize>,
+}
+
+impl ChainedCredential {
+    pub fn new(sources: Vec<Arc<dyn TokenCredential>>) -> ChainedCredential {
+        ChainedCredential {
+            sources,
+            selected: OnceLock::new(),
+        }
+    }
+
+    async fn first_token(&self) -> Result<AccessToken, QueueError> {
+        if let Some(index) = self.selected.get() {
+            return self.sources[*index].get_token().await;
+        }
+        let mut failures = Vec::<String>::new();
+        for (index, source) in self.sources.iter().enumerate() {
+            match source.get_token().await {
+                Ok(token) => {
+                    // if two requests race here they'll both pick the same (first working) credential anyway
+                    let _ = self.selected.set(index);
+                    return Ok(token);
+                }
+                Err(e) => failures.push(format!("{}. {}: {}", index + 1, source.name(), e)),
+            }
+        }
+        Err(QueueError::Credential(match failures.is_empty() {
+            true => "chained credential has no credentials to try".to_string(),
+            false => format!("no credential in the chain worked:\n{}", failures.join("\n")),
+        }))
+    }
+}
+
+impl TokenCredential for ChainedCredential {
+    fn get_token(&self) -> BoxFuture<'_, Result<AccessToken, QueueError>> {
+        Box::pin(self.first_token())
+    }
+}
+
+/// the same idea as `DefaultAzureCredential` in the other sdks: a service principal from environment
+/// variables, then aks workload identity, then managed identity, then whoever is logged in to the azure cli.
+/// The same binary then works on a laptop and in aks without code changes.
+/// fails only if the managed identity's http client can't be built, see `ManagedIdentityCredential::new`.
+pub fn default_credential() -> Result<ChainedCredential, BuildError> {
+    Ok(ChainedCredential::new(vec![
+        Arc::new(EnvironmentCredential::new()),
+        Arc::new(WorkloadIdentityCredential::new()),
+        Arc::new(ManagedIdentityCredential::new()?),
+        Arc::new(AzureCliCredential::new()),
+    ]))
+}
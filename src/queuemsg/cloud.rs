@@ -0,0 +1,174 @@
+use crate::connection_string::DEFAULT_ENDPOINT_SUFFIX;
+
+/// which azure cloud the storage account lives in. This only changes the host name the requests go to,
+/// the canonicalized resource is built from the account and queue names so signing doesn't care.
+/// https://learn.microsoft.com/en-us/azure/storage/common/storage-account-overview#storage-account-endpoints
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum CloudEnvironment {
+    #[default]
+    Public,
+    China,
+    UsGov,
+    Germany,
+    /// anything else, given as the bit after `{account}.queue.` e.g. `core.windows.net`
+    Custom(String),
+    /// an Azure Stack Hub, where the endpoint is `https://{account}.queue.{region}.{fqdn}` and the storage
+    /// service lags behind azure, so only x-ms-versions up to `max_api_version` work.
+    /// https://learn.microsoft.com/en-us/azure-stack/user/azure-stack-acs-differences
+    AzureStack {
+        /// the bit after `{account}.queue.`, e.g. `region.azurestack.corp.contoso.com`
+        endpoint_suffix: String,
+        /// the newest x-ms-version the stack's storage service takes, e.g. `2019-07-07`
+        max_api_version: String,
+    },
+}
+
+impl CloudEnvironment {
+    /// the cloud an endpoint suffix belongs to, e.g. from a connection string's `EndpointSuffix`. Suffixes we
+    /// don't know are `Custom`. Leading dots and trailing slashes are dropped, people paste all sorts.
+    pub fn from_endpoint_suffix(suffix: &str) -> CloudEnvironment {
+        let suffix = suffix.trim().trim_start_matches('.').trim_end_matches('/').to_ascii_lowercase();
+        [
+            CloudEnvironment::Public,
+            CloudEnvironment::China,
+            CloudEnvironment::UsGov,
+            CloudEnvironment::Germany,
+        ]
+        .into_iter()
+        .find(|cloud| cloud.endpoint_suffix() == suffix)
+        .unwrap_or(CloudEnvironment::Custom(suffix))
+    }
+
+    pub fn endpoint_suffix(&self) -> &str {
+        match self {
+            CloudEnvironment::Public => DEFAULT_ENDPOINT_SUFFIX,
+            CloudEnvironment::China => "core.chinacloudapi.cn",
+            CloudEnvironment::UsGov => "core.usgovcloudapi.net",
+            CloudEnvironment::Germany => "core.cloudapi.de",
+            CloudEnvironment::Custom(suffix) => suffix,
+            CloudEnvironment::AzureStack { endpoint_suffix, .. } => endpoint_suffix,
+        }
+    }
+
+    /// the newest x-ms-version this cloud will accept, if it's not keeping up with the public one.
+    pub fn max_api_version(&self) -> Option<&str> {
+        match self {
+            CloudEnvironment::AzureStack { max_api_version, .. } => Some(max_api_version),
+            _ => None,
+        }
+    }
+
+    /// the queue service url for an account in this cloud, `https://{account}.queue.{suffix}`
+    pub fn queue_endpoint(&self, account_name: &str) -> String {
+        format!("https://{}.queue.{}", account_name, self.endpoint_suffix())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    use crate::connection_string::EMULATOR_ACCOUNT_KEY;
+    use crate::{QueueClient, QueueClientBuilder};
+
+    const SOVEREIGN: [(CloudEnvironment, &str); 4] = [
+        (CloudEnvironment::Public, "core.windows.net"),
+        (CloudEnvironment::China, "core.chinacloudapi.cn"),
+        (CloudEnvironment::UsGov, "core.usgovcloudapi.net"),
+        (CloudEnvironment::Germany, "core.cloudapi.de"),
+    ];
+
+    #[test]
+    fn each_sovereign_suffix() {
+        for (cloud, suffix) in SOVEREIGN {
+            assert_eq!(CloudEnvironment::from_endpoint_suffix(suffix), cloud);
+            assert_eq!(CloudEnvironment::from_endpoint_suffix(&format!(" .{}/", suffix.to_uppercase())), cloud);
+            assert_eq!(cloud.queue_endpoint("myaccount"), format!("https://myaccount.queue.{}", suffix));
+
+            // the builder, by cloud and by suffix, and a connection string all end up at the same host
+            let expected = format!("https://myaccount.queue.{}/orders/messages", suffix);
+            let builder = || QueueClientBuilder::new().account_name("myaccount").account_key(EMULATOR_ACCOUNT_KEY);
+            let by_cloud = builder().cloud(cloud.clone()).queue_name("orders").build().unwrap();
+            let by_suffix = builder().endpoint_suffix(suffix).queue_name("orders").build().unwrap();
+            let conn_str = format!(
+                "DefaultEndpointsProtocol=https;AccountName=myaccount;AccountKey={};EndpointSuffix={}",
+                EMULATOR_ACCOUNT_KEY, suffix
+            );
+            let from_conn_str = QueueClient::from_connection_string(&conn_str, "orders").unwrap();
+            for client in [by_cloud, by_suffix, from_conn_str] {
+                assert_eq!(client.queue_url(), expected);
+            }
+        }
+        assert_eq!(
+            CloudEnvironment::from_endpoint_suffix("core.example.org"),
+            CloudEnvironment::Custom("core.example.org".to_string())
+        );
+    }
+
+    fn stopped_clock() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn china_request_goes_to_china_and_signs_the_same_resource() {
+        let mut client = QueueClientBuilder::new()
+            .account_name("myaccount")
+            .account_key(EMULATOR_ACCOUNT_KEY)
+            .cloud(CloudEnvironment::China)
+            .queue_name("orders")
+            .build()
+            .unwrap();
+        client.now = stopped_clock;
+        let query = [("numofmessages", "1".to_string())];
+        let details = client.dry_run(reqwest::Method::GET, "/messages", &query, None).await.unwrap();
+
+        assert_eq!(
+            details.request.url().as_str(),
+            "https://myaccount.queue.core.chinacloudapi.cn/orders/messages?numofmessages=1"
+        );
+        // no sign of the host in what's signed
+        assert_eq!(
+            details.string_to_sign.unwrap(),
+            "GET\n\n\n\n\n\n\n\n\n\n\n\nx-ms-date:Tue, 14 Nov 2023 22:13:20 GMT\nx-ms-version:2021-12-02\n\
+             /myaccount/orders/messages\nnumofmessages:1"
+        );
+        // that string to sign HMAC'd with the key outside of this crate
+        assert_eq!(
+            details.authorization.unwrap(),
+            "SharedKey myaccount:r3LgDGKB2SyhXoPbkS/uFOBtlhiAwmJIK6yp+k0vguc="
+        );
+    }
+
+    #[tokio::test]
+    async fn azure_stack_url_resource_and_version() {
+        let stack = CloudEnvironment::AzureStack {
+            endpoint_suffix: "region.azurestack.corp.contoso.com".to_string(),
+            max_api_version: "2019-07-07".to_string(),
+        };
+        let builder = || {
+            QueueClientBuilder::new()
+                .account_name("account")
+                .account_key(EMULATOR_ACCOUNT_KEY)
+                .cloud(stack.clone())
+                .queue_name("orders")
+        };
+        let client = builder().build().unwrap();
+        let query = [("numofmessages", "1".to_string())];
+        let details = client.dry_run(reqwest::Method::GET, "/messages", &query, None).await.unwrap();
+
+        assert_eq!(
+            details.request.url().as_str(),
+            "https://account.queue.region.azurestack.corp.contoso.com/orders/messages?numofmessages=1"
+        );
+        assert_eq!(details.canonicalized_resource, "/account/orders/messages\nnumofmessages:1");
+        assert!(details.string_to_sign.unwrap().ends_with("\n/account/orders/messages\nnumofmessages:1"));
+        // the default is too new for the stack, so it gets the newest the stack takes
+        assert_eq!(client.api_version(), "2019-07-07");
+        assert_eq!(details.request.headers()["x-ms-version"], "2019-07-07");
+
+        // asking for something newer than it takes is refused rather than quietly changed
+        assert!(builder().api_version("2021-12-02").build().is_err());
+        assert_eq!(builder().api_version("2018-03-28").build().unwrap().api_version(), "2018-03-28");
+    }
+}
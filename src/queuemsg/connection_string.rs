@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use crate::builder::check_plain_http;
+use crate::error::QueueError;
+use crate::CloudEnvironment;
+
+/// the well known azurite / storage emulator account. These are public and the same for everyone.
+/// https://learn.microsoft.com/en-us/azure/storage/common/storage-use-azurite#http-connection-strings
+pub(crate) const EMULATOR_ACCOUNT_NAME: &str = "devstoreaccount1";
+pub(crate) const EMULATOR_ACCOUNT_KEY: &str =
+    "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+pub(crate) const EMULATOR_QUEUE_ENDPOINT: &str = "http://127.0.0.1:10001/devstoreaccount1";
+
+pub(crate) const DEFAULT_ENDPOINT_SUFFIX: &str = "core.windows.net";
+
+/// the bits of a connection string we actually care about for queues.
+/// `queue_endpoint` is the base url of the queue service, without the queue name on the end.
+#[derive(Debug)]
+pub(crate) struct ConnectionString {
+    pub account_name: String,
+    pub account_key: String,
+    pub queue_endpoint: String,
+}
+
+/// connection strings look like:
+/// `DefaultEndpointsProtocol=https;AccountName=...;AccountKey=...;EndpointSuffix=core.windows.net`
+/// the key is base64 so it's usually got `=` on the end, hence splitting on the first `=` only.
+/// https://learn.microsoft.com/en-us/azure/storage/common/storage-configure-connection-string
+pub(crate) fn parse_connection_string(conn_str: &str) -> Result<ConnectionString, QueueError> {
+    let mut pairs = HashMap::<String, String>::new();
+    for part in conn_str.split(';') {
+        let part = part.trim();
+        // trailing ; is common when people copy these about
+        if part.is_empty() {
+            continue;
+        }
+        let (key, value) = match part.split_once('=') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => {
+                return Err(QueueError::InvalidConfig(format!(
+                    "connection string segment '{}' is not in key=value form",
+                    part
+                )))
+            }
+        };
+        if pairs.insert(key.to_string(), value.to_string()).is_some() {
+            return Err(QueueError::InvalidConfig(format!(
+                "connection string contains '{}' more than once",
+                key
+            )));
+        }
+    }
+
+    if let Some(dev) = pairs.get("UseDevelopmentStorage") {
+        return match dev.as_str() {
+            "true" => Ok(ConnectionString {
+                account_name: EMULATOR_ACCOUNT_NAME.to_string(),
+                account_key: EMULATOR_ACCOUNT_KEY.to_string(),
+                queue_endpoint: EMULATOR_QUEUE_ENDPOINT.to_string(),
+            }),
+            other => Err(QueueError::InvalidConfig(format!(
+                "UseDevelopmentStorage must be 'true' if present, got '{}'",
+                other
+            ))),
+        };
+    }
+
+    let account_name = required(&pairs, "AccountName")?;
+    let account_key = required(&pairs, "AccountKey")?;
+
+    let protocol = match pairs.get("DefaultEndpointsProtocol").map(String::as_str) {
+        None | Some("https") => "https",
+        Some("http") => "http",
+        Some(other) => {
+            return Err(QueueError::InvalidConfig(format!(
+                "unknown DefaultEndpointsProtocol '{}', expected http or https",
+                other
+            )))
+        }
+    };
+
+    // an explicit QueueEndpoint wins over anything we'd build from the account name
+    let queue_endpoint = match pairs.get("QueueEndpoint") {
+        Some(endpoint) => endpoint.trim_end_matches('/').to_string(),
+        None => {
+            let cloud = pairs
+                .get("EndpointSuffix")
+                .map(|suffix| CloudEnvironment::from_endpoint_suffix(suffix))
+                .unwrap_or_default();
+            format!("{}://{}.queue.{}", protocol, account_name, cloud.endpoint_suffix())
+        }
+    };
+
+    // no way to opt in to plain http from a connection string, that needs the builder
+    check_plain_http(&queue_endpoint, false)?;
+    Ok(ConnectionString {
+        account_name,
+        account_key,
+        queue_endpoint,
+    })
+}
+
+fn required(pairs: &HashMap<String, String>, key: &str) -> Result<String, QueueError> {
+    match pairs.get(key) {
+        Some(value) if !value.is_empty() => Ok(value.clone()),
+        _ => Err(QueueError::InvalidConfig(format!(
+            "connection string is missing {}",
+            key
+        ))),
+    }
+}
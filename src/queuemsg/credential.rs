@@ -0,0 +1,331 @@
+use std::fmt;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use futures::future::BoxFuture;
+
+use crate::builder::decode_account_key;
+use crate::{
+    canonical_headers, canonical_resource_lite, construct_lite_signature, construct_signature, hmac_256, AccountKey,
+    QueueError, SignedHeaders, TokenCredential, OAUTH_MIN_API_VERSION,
+};
+
+/// what a `Credential` gets to look at when authorizing a request. The client fills this in just before sending.
+/// `canonical_resource` is already built for the request (path style urls, query parameters and all), so a
+/// SharedKey style credential only needs to bring its key.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct RequestParts<'a> {
+    pub method: &'a str,
+    pub account_name: &'a str,
+    pub content_length: usize,
+    /// the time that will be sent, as x-ms-date or Date (unless `Credential::sends_date` says not to)
+    pub date: &'a str,
+    /// true when `date` is going in the standard Date header instead of x-ms-date (`ClientOptions::date_header`),
+    /// in which case it fills the Date line of a shared key string to sign.
+    pub date_header: bool,
+    /// the x-ms-version that will be sent
+    pub api_version: &'a str,
+    pub canonical_resource: String,
+    /// `canonical_resource` without the query, `/{account}/{queue}{path}`, for signing schemes that treat the
+    /// query differently (SharedKeyLite)
+    pub resource_path: String,
+    /// the query parameters of the request, not url encoded
+    pub query: Vec<(String, String)>,
+    /// every x-ms- header being sent, x-ms-date and x-ms-version included, names lowercased and sorted by name.
+    /// These are exactly what goes on the wire, sign all of them (`canonical_headers`) or none.
+    pub ms_headers: Vec<(String, String)>,
+    /// standard (not x-ms-) headers being sent, some of which have their own line in a shared key string to
+    /// sign, e.g. Content-Type or If-Match. Most operations don't send any.
+    pub headers: Vec<(String, String)>,
+}
+
+/// what a `Credential` wants added to the request: headers (`Authorization` usually) and/or query parameters
+/// (SAS tokens). Both get added exactly as given.
+#[derive(Debug, Default)]
+pub struct Authorization {
+    pub headers: Vec<(String, String)>,
+    pub query: Vec<(String, String)>,
+    /// what was signed, if anything, for `ClientOptions::debug_auth`. Never the key or the signature.
+    pub string_to_sign: Option<String>,
+}
+
+/// anything that can authorize a request to the queue service. The client calls `authorize` on every request
+/// just before it's sent, so implementations are free to fetch keys or tokens from wherever (vault etc.) as
+/// long as they cache sensibly.
+/// shared key signing, bearer tokens and SAS are all just implementations of this, and the future is boxed so
+/// the client can hold any of them as an `Arc<dyn Credential>`.
+pub trait Credential: Send + Sync + fmt::Debug {
+    fn authorize<'a>(&'a self, request: &'a RequestParts<'a>) -> BoxFuture<'a, Result<Authorization, QueueError>>;
+
+    /// the oldest x-ms-version this kind of auth works with, if it cares. The builder checks this.
+    fn min_api_version(&self) -> Option<&str> {
+        None
+    }
+
+    /// whether the request needs an x-ms-date header. Anything signed does, SAS doesn't.
+    fn sends_date(&self) -> bool {
+        true
+    }
+
+    /// swap in a new account key for every request from now on, see `QueueClient::set_account_key`.
+    fn set_account_key(&self, _account_key: &str) -> Result<(), QueueError> {
+        Err(QueueError::InvalidConfig("this client doesn't authorize with an account key".to_string()))
+    }
+
+    /// swap in a new SAS token for every request from now on, see `QueueClient::set_sas_token`.
+    fn set_sas_token(&self, _sas_token: &str) -> Result<(), QueueError> {
+        Err(QueueError::InvalidConfig("this client doesn't authorize with a SAS token".to_string()))
+    }
+
+    /// the account key credential underneath, if that's what this is. Things that sign with the key outside of
+    /// a request (`QueueClient::generate_sas`) need it.
+    fn shared_key(&self) -> Option<&SharedKeyCredential> {
+        None
+    }
+
+    /// azure answered 403 AuthenticationFailed to something this authorized. Return true if it's worth one
+    /// more go, i.e. the credential has thrown away whatever it had and will fetch something new.
+    fn authentication_failed(&self) -> bool {
+        false
+    }
+}
+
+/// the original flavour: sign the request with the account key (`construct_signature` + `hmac_256`) and send
+/// `Authorization: SharedKey account:signature`.
+/// clones share the key, so `set_account_key` on one rotates all of them.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key
+#[derive(Clone)]
+pub struct SharedKeyCredential {
+    account_name: String,
+    account_key: Arc<RwLock<Vec<u8>>>,
+    mode: SigningMode,
+}
+
+/// which flavour of shared key signature `SharedKeyCredential` makes. Both are just as secure, Lite signs
+/// fewer headers and has a much shorter string to sign, which some older proxies and tooling want and which
+/// is easier to debug.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SigningMode {
+    #[default]
+    SharedKey,
+    SharedKeyLite,
+}
+
+impl SharedKeyCredential {
+    /// `account_key` is the base64 key from the portal. It's decoded here, so a key that's been mangled
+    /// on the way in is a `QueueError::InvalidAccountKey` now rather than on the first send.
+    pub fn new(account_name: &str, account_key: &str) -> Result<SharedKeyCredential, QueueError> {
+        Ok(SharedKeyCredential::from_key_bytes(account_name, decode_account_key(account_key)?))
+    }
+
+    /// same as `new` but signing with SharedKeyLite, for talking to things that standardised on the lite scheme.
+    /// `Authorization: SharedKeyLite account:signature`
+    pub fn lite(account_name: &str, account_key: &str) -> Result<SharedKeyCredential, QueueError> {
+        Ok(SharedKeyCredential::new(account_name, account_key)?.with_signing_mode(SigningMode::SharedKeyLite))
+    }
+
+    /// for a key that didn't come as base64, see `AccountKey`.
+    pub fn from_account_key(account_name: &str, account_key: AccountKey) -> SharedKeyCredential {
+        SharedKeyCredential::from_key_bytes(account_name, account_key.into_bytes())
+    }
+
+    pub(crate) fn from_key_bytes(account_name: &str, account_key: Vec<u8>) -> SharedKeyCredential {
+        SharedKeyCredential {
+            account_name: account_name.to_string(),
+            account_key: Arc::new(RwLock::new(account_key)),
+            mode: SigningMode::default(),
+        }
+    }
+
+    /// sign with SharedKeyLite instead, or back again.
+    pub fn with_signing_mode(mut self, mode: SigningMode) -> SharedKeyCredential {
+        self.mode = mode;
+        self
+    }
+
+    pub fn account_name(&self) -> &str {
+        &self.account_name
+    }
+
+    pub fn signing_mode(&self) -> SigningMode {
+        self.mode
+    }
+
+    /// sign everything from now on with a different key, e.g. after rotating keys in the portal. Requests
+    /// already signed carry on with the old one. Checked the same as in `new`, a bad key leaves the old one in place.
+    pub fn set_account_key(&self, account_key: &str) -> Result<(), QueueError> {
+        let account_key = decode_account_key(account_key)?;
+        *self.account_key.write().unwrap_or_else(PoisonError::into_inner) = account_key;
+        Ok(())
+    }
+
+    /// base64 hmac-sha256 of `string_to_sign` with the account key. Requests and SAS tokens both end up here.
+    pub(crate) fn sign(&self, string_to_sign: &str) -> Result<String, QueueError> {
+        hmac_256(string_to_sign, &self.account_key.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// `authorize` without the future, nothing here needs to wait on anything.
+    pub(crate) fn authorization(&self, request: &RequestParts<'_>) -> Result<Authorization, QueueError> {
+        let headers = canonical_headers(&request.ms_headers);
+        let signed_headers = SignedHeaders::new(
+            request.content_length,
+            request.date_header.then(|| request.date.to_string()),
+            &request.headers,
+        );
+        let (scheme, auth_str) = match self.mode {
+            SigningMode::SharedKey => (
+                "SharedKey",
+                construct_signature(
+                    request.method,
+                    &signed_headers,
+                    headers,
+                    request.canonical_resource.clone(),
+                ),
+            ),
+            SigningMode::SharedKeyLite => (
+                "SharedKeyLite",
+                construct_lite_signature(
+                    request.method,
+                    &signed_headers,
+                    headers,
+                    canonical_resource_lite(&request.resource_path, &request.query),
+                ),
+            ),
+        };
+        let encoded_auth = self.sign(&auth_str)?;
+        Ok(Authorization {
+            headers: vec![(
+                "Authorization".to_string(),
+                format!("{} {}:{}", scheme, self.account_name, encoded_auth),
+            )],
+            query: Vec::new(),
+            string_to_sign: Some(auth_str),
+        })
+    }
+}
+
+// the account key must never end up in a log line because someone `{:?}`'d a client.
+impl fmt::Debug for SharedKeyCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedKeyCredential")
+            .field("account_name", &self.account_name)
+            .field("account_key", &"<redacted>")
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+impl Credential for SharedKeyCredential {
+    fn authorize<'a>(&'a self, request: &'a RequestParts<'a>) -> BoxFuture<'a, Result<Authorization, QueueError>> {
+        Box::pin(async move { self.authorization(request) })
+    }
+
+    fn set_account_key(&self, account_key: &str) -> Result<(), QueueError> {
+        SharedKeyCredential::set_account_key(self, account_key)
+    }
+
+    fn shared_key(&self) -> Option<&SharedKeyCredential> {
+        Some(self)
+    }
+}
+
+/// AAD bearer auth using tokens from a `TokenCredential`. No signing at all, the token is the proof.
+/// x-ms-date and x-ms-version still have to be sent.
+#[derive(Debug, Clone)]
+pub(crate) struct BearerCredential(pub Arc<dyn TokenCredential>);
+
+impl Credential for BearerCredential {
+    fn authorize<'a>(&'a self, _request: &'a RequestParts<'a>) -> BoxFuture<'a, Result<Authorization, QueueError>> {
+        Box::pin(async move {
+            let token = self.0.get_token().await?;
+            Ok(Authorization {
+                headers: vec![("Authorization".to_string(), format!("Bearer {}", token.token))],
+                query: Vec::new(),
+                string_to_sign: None,
+            })
+        })
+    }
+
+    fn min_api_version(&self) -> Option<&str> {
+        Some(OAUTH_MIN_API_VERSION)
+    }
+}
+
+/// a pre-generated SAS token, e.g. `sv=2019-02-02&st=...&se=...&sp=a&sig=...`, so the account key never has to
+/// leave whoever made the token. Nothing is signed, the token just gets stuck on the end of the url.
+/// clones share the token, so `set_token` on one changes all of them.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/delegate-access-with-shared-access-signature
+#[derive(Clone)]
+pub struct SasCredential {
+    /// already url decoded, reqwest encodes them again on the way out
+    pairs: Arc<RwLock<Vec<(String, String)>>>,
+}
+
+impl SasCredential {
+    /// the token as the portal gives it to you, with or without the leading `?`, or a whole SAS url like
+    /// `https://myaccount.queue.core.windows.net/myqueue?sv=...&sig=...` in which case only the query is used.
+    /// An expired or wrong token isn't something we can spot here, azure answers 403 AuthenticationFailed and the
+    /// body (in the `QueueError::Service`) says whether the signature or the time window was the problem.
+    pub fn new(sas_token: &str) -> Result<SasCredential, QueueError> {
+        Ok(SasCredential {
+            pairs: Arc::new(RwLock::new(parse_sas_token(sas_token)?)),
+        })
+    }
+
+    /// use a different token from now on, e.g. the next one from your secrets service. Checked the same as in
+    /// `new`, a bad token leaves the old one in place.
+    pub fn set_token(&self, sas_token: &str) -> Result<(), QueueError> {
+        let pairs = parse_sas_token(sas_token)?;
+        *self.pairs.write().unwrap_or_else(PoisonError::into_inner) = pairs;
+        Ok(())
+    }
+
+    fn pairs(&self) -> Vec<(String, String)> {
+        self.pairs.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+fn parse_sas_token(sas_token: &str) -> Result<Vec<(String, String)>, QueueError> {
+    let sas_token = sas_token.trim();
+    let sas_token = match sas_token.starts_with("https://") || sas_token.starts_with("http://") {
+        true => sas_token.split_once('?').map(|(_, query)| query).unwrap_or_default(),
+        false => sas_token.trim_start_matches('?'),
+    };
+    // borrow reqwest's url parser to do the percent decoding for us
+    let url = reqwest::Url::parse(&format!("https://sas.invalid/?{}", sas_token))
+        .map_err(|e| QueueError::InvalidConfig(format!("SAS token is not a valid query string: {}", e)))?;
+    let pairs: Vec<(String, String)> = url.query_pairs().map(|(k, v)| (k.into_owned(), v.into_owned())).collect();
+    if !pairs.iter().any(|(name, _)| name == "sig") {
+        return Err(QueueError::InvalidConfig("SAS token has no sig parameter".to_string()));
+    }
+    Ok(pairs)
+}
+
+// the sig is as good as a key until it expires
+impl fmt::Debug for SasCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self.pairs().into_iter().map(|(name, _)| name).collect();
+        f.debug_struct("SasCredential").field("parameters", &names).finish()
+    }
+}
+
+impl Credential for SasCredential {
+    fn authorize<'a>(&'a self, _request: &'a RequestParts<'a>) -> BoxFuture<'a, Result<Authorization, QueueError>> {
+        Box::pin(async move {
+            Ok(Authorization {
+                headers: Vec::new(),
+                query: self.pairs(),
+                string_to_sign: None,
+            })
+        })
+    }
+
+    fn sends_date(&self) -> bool {
+        false
+    }
+
+    fn set_sas_token(&self, sas_token: &str) -> Result<(), QueueError> {
+        self.set_token(sas_token)
+    }
+}
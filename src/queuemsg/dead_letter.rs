@@ -0,0 +1,38 @@
+use crate::{QueueClient, QueueError, QueueMessage};
+
+/// how many times azure functions lets a message be picked up before it's moved to `<queue>-poison`.
+/// https://learn.microsoft.com/en-us/azure/azure-functions/functions-bindings-storage-queue-trigger#poison-messages
+pub const DEFAULT_MAX_DEQUEUE_COUNT: u32 = 5;
+
+impl QueueMessage {
+    /// true once the message has been handed out more than `max_dequeue_count` times, i.e. it's been tried
+    /// that many times already and never deleted. Check it before handling a message, not after.
+    pub fn exceeds_dequeue_count(&self, max_dequeue_count: u32) -> bool {
+        self.dequeue_count > max_dequeue_count
+    }
+}
+
+impl QueueClient {
+    /// move a message that keeps failing (see `QueueMessage::exceeds_dequeue_count`) onto `dlq`, a separate
+    /// queue for a person to look at, the same idea as azure functions' `<queue>-poison`. The text is sent as it
+    /// was read, through `dlq`'s own message encoding. It's a put there and then a delete here with the
+    /// message's pop receipt, two calls that can't be made one:
+    /// - the put fails: nothing's changed, the message is still here and comes back after its visibility timeout
+    /// - the put works but the delete doesn't: it's on both queues. It'll come back here, and be moved again, so
+    ///   the dead letter queue can end up with the same message twice. At least once, never lost.
+    ///
+    /// either way the error is the one from the call that failed.
+    pub async fn move_to_dead_letter(&self, msg: &QueueMessage, dlq: &QueueClient) -> Result<(), QueueError> {
+        dlq.send_message(msg.message_text.clone()).await?;
+        self.delete_message(&msg.message_id, &msg.pop_receipt).await.map_err(|e| {
+            log::warn!(
+                "message {} is on dead letter queue {} but couldn't be deleted from {}: {}",
+                msg.message_id,
+                dlq.queue_name(),
+                self.queue_name(),
+                e
+            );
+            e
+        })
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{xml, QueueError};
+
+/// wrap a 403 AuthenticationFailed with what we signed, so it can be put side by side with what azure says it
+/// signed (it puts that in `AuthenticationErrorDetail`):
+/// ```text
+/// <AuthenticationErrorDetail>The MAC signature found in the HTTP request '...' is not the same as any
+/// computed signature. Server used following string to sign: 'POST
+///
+/// ...'.</AuthenticationErrorDetail>
+/// ```
+/// anything that isn't an authentication failure is handed back untouched.
+pub(crate) fn explain(error: QueueError, string_to_sign: Option<String>) -> QueueError {
+    let string_to_sign = match (error.error_code(), string_to_sign) {
+        (Some("AuthenticationFailed"), Some(string_to_sign)) => string_to_sign,
+        _ => return error,
+    };
+    let server_string_to_sign = match &error {
+        QueueError::Service { body, .. } => server_string_to_sign(body),
+        _ => None,
+    };
+    let diff = server_string_to_sign.as_deref().map(|theirs| diff_lines(&string_to_sign, theirs));
+    QueueError::AuthenticationFailed {
+        error: Box::new(error),
+        string_to_sign,
+        server_string_to_sign,
+        diff,
+    }
+}
+
+/// the bit between the quotes after "string to sign:". The string to sign is full of newlines, and could have
+/// quotes in it, so it's everything up to the last quote rather than the next one.
+fn server_string_to_sign(body: &str) -> Option<String> {
+    let detail = xml::unescape(xml::element(body, "AuthenticationErrorDetail")?);
+    let marker = "string to sign: '";
+    let start = detail.to_ascii_lowercase().find(marker)? + marker.len();
+    let end = detail.rfind('\'').filter(|end| *end >= start)?;
+    Some(detail[start..end].to_string())
+}
+
+/// line by line, numbered since most lines of a string to sign are empty and it's the position that matters:
+/// ```text
+///   1  POST
+/// - 4  12
+/// + 4  0
+/// ```
+/// `-` is ours and `+` is azure's. Lines that match are left in so you can count.
+fn diff_lines(ours: &str, theirs: &str) -> String {
+    let ours: Vec<&str> = ours.split('\n').collect();
+    let theirs: Vec<&str> = theirs.split('\n').collect();
+    let mut lines = Vec::<String>::new();
+    for index in 0..ours.len().max(theirs.len()) {
+        match (ours.get(index), theirs.get(index)) {
+            (Some(a), Some(b)) if a == b => lines.push(format!("  {}  {}", index + 1, a)),
+            (a, b) => {
+                if let Some(a) = a {
+                    lines.push(format!("- {}  {}", index + 1, a));
+                }
+                if let Some(b) = b {
+                    lines.push(format!("+ {}  {}", index + 1, b));
+                }
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn forbidden(body: &str) -> QueueError {
+        QueueError::Service { status: 403, headers: HashMap::new(), body: body.to_string() }
+    }
+
+    #[test]
+    fn authentication_failure_gets_a_diff_against_azures_string_to_sign() {
+        // azure's string to sign has a content length of 0 where we signed an empty line, and x-ms-date is its
+        // own so it matches
+        let theirs = "POST\n\n\n0\n\n\n\n\n\n\n\n\nx-ms-date:Tue, 14 Nov 2023 22:13:20 GMT\nx-ms-version:2021-12-02\n\
+                      /myaccount/myqueue/messages";
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?><Error><Code>AuthenticationFailed</Code><Message>Server \
+             failed to authenticate the request.</Message><AuthenticationErrorDetail>The MAC signature found in \
+             the HTTP request 'abc=' is not the same as any computed signature. Server used following string to \
+             sign: '{}'.</AuthenticationErrorDetail></Error>",
+            xml::escape(theirs)
+        );
+        let ours = theirs.replace("\n0\n", "\n\n");
+
+        let explained = explain(forbidden(&body), Some(ours.clone()));
+        let (string_to_sign, server_string_to_sign, diff) = match explained {
+            QueueError::AuthenticationFailed { string_to_sign, server_string_to_sign, diff, .. } => {
+                (string_to_sign, server_string_to_sign, diff)
+            }
+            other => panic!("expected AuthenticationFailed, got {:?}", other),
+        };
+        assert_eq!(string_to_sign, ours);
+        assert_eq!(server_string_to_sign.as_deref(), Some(theirs));
+        let diff = diff.unwrap();
+        let changed: Vec<&str> = diff.lines().filter(|line| !line.starts_with(' ')).collect();
+        assert_eq!(changed, vec!["- 4  ", "+ 4  0"]);
+        assert!(diff.starts_with("  1  POST\n"));
+        assert!(diff.ends_with("  15  /myaccount/myqueue/messages"));
+    }
+
+    #[test]
+    fn anything_else_is_left_alone() {
+        let not_auth = "<Error><Code>QueueNotFound</Code></Error>";
+        let error = explain(forbidden(not_auth), Some("GET\n".to_string()));
+        assert!(matches!(error, QueueError::Service { .. }));
+        // debug_auth off, nothing was kept to compare
+        let auth = "<Error><Code>AuthenticationFailed</Code></Error>";
+        assert!(matches!(explain(forbidden(auth), None), QueueError::Service { .. }));
+        // no string to sign from azure, ours is still there but there's nothing to diff
+        match explain(forbidden(auth), Some("GET\n".to_string())) {
+            QueueError::AuthenticationFailed { server_string_to_sign: None, diff: None, .. } => {}
+            other => panic!("expected AuthenticationFailed without a diff, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use futures::future::BoxFuture;
+
+use crate::token::{request_aad_token, AccessToken, TokenCache, TokenCredential, DEFAULT_AUTHORITY_HOST, STORAGE_SCOPE};
+use crate::{optional_env, required_env, QueueError};
+
+/// a service principal with a client secret, from the same environment variables the other azure sdks use:
+/// `AZURE_TENANT_ID`, `AZURE_CLIENT_ID` and `AZURE_CLIENT_SECRET` (and optionally `AZURE_AUTHORITY_HOST`).
+/// the variables are read when a token is needed, so a missing one shows up as an error from `get_token`
+/// rather than at construction - which is what lets `ChainedCredential` skip past it.
+#[derive(Debug, Default)]
+pub struct EnvironmentCredential {
+    http: reqwest::Client,
+    cache: TokenCache,
+}
+
+impl EnvironmentCredential {
+    pub fn new() -> EnvironmentCredential {
+        EnvironmentCredential::default()
+    }
+
+    async fn request_token(&self) -> Result<AccessToken, QueueError> {
+        let tenant_id = required_env("AZURE_TENANT_ID")?;
+        let client_id = required_env("AZURE_CLIENT_ID")?;
+        let client_secret = required_env("AZURE_CLIENT_SECRET")?;
+        let authority_host =
+            optional_env("AZURE_AUTHORITY_HOST")?.unwrap_or_else(|| DEFAULT_AUTHORITY_HOST.to_string());
+        let form = [
+            ("grant_type", "client_credentials"),
+            ("client_id", client_id.as_str()),
+            ("client_secret", client_secret.as_str()),
+            ("scope", STORAGE_SCOPE),
+        ];
+        request_aad_token(&self.http, &authority_host, &tenant_id, &form).await
+    }
+}
+
+impl TokenCredential for EnvironmentCredential {
+    fn get_token(&self) -> BoxFuture<'_, Result<AccessToken, QueueError>> {
+        Box::pin(self.cache.get_or_refresh(|| self.request_token()))
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{xml, BuildError, QueueMessage};
+
+/// everything that can go wrong talking to the queue service ends up as one of these.
+#[derive(Debug)]
+pub enum QueueError {
+    /// the client was configured with something that can't possibly work (bad connection string etc.)
+    /// the string says what was wrong.
+    InvalidConfig(String),
+    /// the account key isn't a usable key, usually a copy/paste accident. The string says what's wrong with it.
+    InvalidAccountKey(String),
+    /// couldn't set up the hmac to sign the request with the decoded key.
+    Hmac,
+    /// couldn't get a token out of a `TokenCredential` (managed identity etc.), the string says why.
+    Credential(String),
+    /// we never got a response from azure - dns, tls, connection reset and friends.
+    Transport(reqwest::Error),
+    /// azure answered but didn't like it. `body` is the raw response, normally xml with an error code.
+    /// `headers` are the response headers with lowercased names, e.g. `retry-after` or `x-ms-request-id`.
+    Service {
+        status: u16,
+        headers: HashMap<String, String>,
+        body: String,
+    },
+    /// azure answered with a 2xx but we couldn't make sense of what it sent back.
+    InvalidResponse(String),
+    /// only with `ClientOptions::debug_auth` on: a 403 AuthenticationFailed (`error`, the usual `Service` error)
+    /// along with the string we signed and, when azure said, the one it signed. `diff` goes through them line
+    /// by line, the first `-`/`+` pair is usually the problem (a header we sent but didn't sign, a length...).
+    /// none of it has the key or the signature in it.
+    AuthenticationFailed {
+        error: Box<QueueError>,
+        string_to_sign: String,
+        server_string_to_sign: Option<String>,
+        diff: Option<String>,
+    },
+    /// a message body that isn't json for the type asked for (`get_typed`). `message` is the whole message,
+    /// pop receipt and all, so it can be deleted or moved somewhere for a person to look at. It's still on the
+    /// queue and will be back once its visibility timeout is up.
+    Deserialize {
+        message: Box<QueueMessage>,
+        error: serde_json::Error,
+    },
+    /// a message whose text isn't in the client's `MessageEncoding` (not base64, or not utf-8 once decoded),
+    /// usually a producer using a different encoding. `message` has the id and pop receipt and the text as azure
+    /// sent it, so it can be deleted or moved to a dead letter queue. It's hidden like the rest of the batch and
+    /// will be back once its visibility timeout is up.
+    Decode {
+        message: Box<QueueMessage>,
+        error: String,
+    },
+    /// azure's 400 PopReceiptMismatch (`error`) from a delete or update: the pop receipt isn't the message's
+    /// latest one. The message became visible again and someone else got it, or it was updated since, so for a
+    /// delete it usually means another consumer has it and there's nothing to do. A message that's gone
+    /// altogether is a plain `Service` 404 MessageNotFound instead.
+    PopReceiptMismatch {
+        message_id: String,
+        error: Box<QueueError>,
+    },
+    /// azure's 404 QueueNotFound (`error`), the queue isn't there. Most operations can get this, but only
+    /// `delete_queue` and `get_properties` turn it into this variant, everything else leaves it as `Service`.
+    QueueNotFound {
+        queue_name: String,
+        error: Box<QueueError>,
+    },
+    /// azure's 409 QueueBeingDeleted (`error`) from `create_queue` or `delete_queue`: the queue was deleted
+    /// recently and azure hasn't finished, which can take 30 seconds or more. Try again later, or see
+    /// `QueueClient::wait_for_deletion`.
+    QueueBeingDeleted {
+        queue_name: String,
+        error: Box<QueueError>,
+    },
+    /// a queue metadata name that isn't a valid C# identifier (letters, digits and `_`, not starting with a
+    /// digit), so azure would refuse it. Caught before anything is sent.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/naming-queues-and-metadata#metadata-names
+    InvalidMetadataName(String),
+    /// a value `put_typed` couldn't turn into json, e.g. a map with keys that aren't strings. Nothing was sent.
+    Serialize(serde_json::Error),
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueError::InvalidConfig(msg) => write!(f, "invalid queue client configuration: {}", msg),
+            QueueError::InvalidAccountKey(msg) => write!(f, "invalid account key: {}", msg),
+            QueueError::Hmac => write!(f, "couldn't create hmac instance from account key"),
+            QueueError::Credential(msg) => write!(f, "couldn't get an access token: {}", msg),
+            QueueError::Transport(e) => write!(f, "request to queue service failed: {}", e),
+            QueueError::Service { status, body, .. } => match (self.error_code(), xml::element(body, "Message")) {
+                (Some(code), Some(message)) => {
+                    write!(f, "queue service returned {} {}: {}", status, code, message.trim())
+                }
+                _ => write!(f, "queue service returned {}: {}", status, body),
+            },
+            QueueError::InvalidResponse(msg) => write!(f, "unexpected response from queue service: {}", msg),
+            QueueError::AuthenticationFailed { error, string_to_sign, diff, .. } => match diff {
+                Some(diff) => write!(f, "{}\nour string to sign (-) against azure's (+):\n{}", error, diff),
+                None => write!(f, "{}\nour string to sign:\n{}", error, string_to_sign),
+            },
+            QueueError::Deserialize { message, error } => {
+                write!(f, "couldn't deserialize message {}: {}", message.message_id, error)
+            }
+            QueueError::Decode { message, error } => {
+                write!(f, "couldn't decode message {}: {}", message.message_id, error)
+            }
+            QueueError::PopReceiptMismatch { message_id, error } => {
+                write!(f, "pop receipt for message {} is out of date: {}", message_id, error)
+            }
+            QueueError::QueueNotFound { queue_name, error } => {
+                write!(f, "queue {} doesn't exist: {}", queue_name, error)
+            }
+            QueueError::QueueBeingDeleted { queue_name, error } => {
+                write!(f, "queue {} is still being deleted: {}", queue_name, error)
+            }
+            QueueError::InvalidMetadataName(name) => write!(
+                f,
+                "metadata name '{}' must be letters, digits and underscores, not starting with a digit",
+                name
+            ),
+            QueueError::Serialize(e) => write!(f, "couldn't serialize message: {}", e),
+        }
+    }
+}
+
+impl QueueError {
+    /// azure puts an error code in the response body like `<Error><Code>AuthenticationFailed</Code>...`
+    /// this digs it out for service errors so you can match on it.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/queue-service-error-codes
+    pub fn error_code(&self) -> Option<&str> {
+        match self {
+            QueueError::Service { body, .. } => xml::element(body, "Code"),
+            QueueError::AuthenticationFailed { error, .. }
+            | QueueError::PopReceiptMismatch { error, .. }
+            | QueueError::QueueNotFound { error, .. }
+            | QueueError::QueueBeingDeleted { error, .. } => error.error_code(),
+            _ => None,
+        }
+    }
+
+    /// azure's `x-ms-request-id` for a service error. Microsoft support need this to find the request.
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            QueueError::Service { headers, .. } => headers.get("x-ms-request-id").map(String::as_str),
+            QueueError::AuthenticationFailed { error, .. }
+            | QueueError::PopReceiptMismatch { error, .. }
+            | QueueError::QueueNotFound { error, .. }
+            | QueueError::QueueBeingDeleted { error, .. } => error.request_id(),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for QueueError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QueueError::Transport(e) => Some(e),
+            QueueError::AuthenticationFailed { error, .. }
+            | QueueError::PopReceiptMismatch { error, .. }
+            | QueueError::QueueNotFound { error, .. }
+            | QueueError::QueueBeingDeleted { error, .. } => Some(error.as_ref()),
+            QueueError::Deserialize { error, .. } => Some(error),
+            QueueError::Serialize(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<BuildError> for QueueError {
+    fn from(e: BuildError) -> Self {
+        match e {
+            BuildError::InvalidAccountKey(msg) => QueueError::InvalidAccountKey(msg),
+            other => QueueError::InvalidConfig(other.to_string()),
+        }
+    }
+}
+
+impl From<reqwest::Error> for QueueError {
+    fn from(e: reqwest::Error) -> Self {
+        QueueError::Transport(e)
+    }
+}
@@ -1,57 +1,268 @@
-use std::fmt::Error;
-use chrono::{Local, DateTime};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
 use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
 use sha2::Sha256;
 
 use base64::{Engine as _, engine::general_purpose};
+use bytes::Bytes;
+
+mod account_key;
+mod acl;
+mod azure_cli;
+mod bearer;
+#[cfg(feature = "blocking")]
+mod blocking;
+mod builder;
+mod chained;
+mod cloud;
+mod connection_string;
+mod credential;
+mod dead_letter;
+mod debug_auth;
+mod environment;
+mod error;
+mod managed_identity;
+mod management;
+mod messages;
+mod options;
+mod poll;
+mod pool;
+mod renewing_sas;
+mod retry;
+mod sas;
+mod service;
+mod sign;
+mod token;
+#[cfg(feature = "serde")]
+mod typed;
+mod workload_identity;
+mod xml;
+
+pub use account_key::AccountKey;
+pub use acl::{PolicyPermissions, SignedIdentifier, StoredAccessPolicy, MAX_SIGNED_IDENTIFIERS};
+pub use azure_cli::AzureCliCredential;
+pub use bearer::BearerTokenCredential;
+#[cfg(feature = "blocking")]
+pub use blocking::BlockingQueueClient;
+pub use builder::{BuildError, QueueClientBuilder};
+pub use chained::{default_credential, ChainedCredential};
+pub use cloud::CloudEnvironment;
+pub use credential::{Authorization, Credential, RequestParts, SasCredential, SharedKeyCredential, SigningMode};
+pub use dead_letter::DEFAULT_MAX_DEQUEUE_COUNT;
+pub use environment::EnvironmentCredential;
+pub use error::QueueError;
+pub use managed_identity::ManagedIdentityCredential;
+pub use management::{CreateQueueOutcome, QueueMetadata, QueueProperties, DELETION_POLL_INTERVAL};
+pub use messages::{
+    parse_messages_list, parse_sent_message, MessageEncoding, PeekedMessage, QueueMessage, ReceiveOptions,
+    SendOptions, SentMessage, UpdateResult, MAX_CLEAR_CALLS,
+};
+pub use options::{ClientOptions, DEFAULT_USER_AGENT};
+pub use poll::PollOptions;
+pub use pool::{PoolError, QueuePool, Router};
+pub use renewing_sas::{RenewalReason, RenewingSasCredential, SasRenewal};
+pub use retry::RetryPolicy;
+pub use sas::{
+    AccountSasBuilder, AccountSasPermissions, AccountSasResourceTypes, AccountSasServices, QueueSasBuilder,
+    QueueSasPermissions, SasProtocol,
+};
+pub use service::{ListQueuesOptions, QueueItem, QueueServiceClient, QueuesPage, MAX_LIST_RESULTS};
+pub use sign::sign_request;
+pub use token::{AccessToken, TokenCredential, OAUTH_MIN_API_VERSION};
+pub use workload_identity::WorkloadIdentityCredential;
+
+/// what came back from a successful call. Azure likes 201 for puts so don't assume 200.
+/// header names are lowercased, some operations (update message, metadata) only answer in headers. Things like
+/// `x-ms-version` and `date` are in there too.
+/// `request_id` is azure's `x-ms-request-id`, which is what microsoft support will ask for.
+/// `body` is the bytes as they came, `text()` for the xml.
+#[derive(Debug, Clone)]
+pub struct QueueResponse {
+    pub status: u16,
+    pub request_id: Option<String>,
+    pub headers: HashMap<String, String>,
+    pub body: Bytes,
+}
+
+impl QueueResponse {
+    /// the body as text. Azure only ever sends utf-8 xml (or nothing), anything else is an
+    /// `InvalidResponse`.
+    pub fn text(&self) -> Result<&str, QueueError> {
+        std::str::from_utf8(&self.body)
+            .map_err(|e| QueueError::InvalidResponse(format!("response body isn't utf-8: {}", e)))
+    }
+}
 
-static STORAGE_ACCOUNT_NAME: &'static str = "my-storage-account-name";
-static STORAGE_ACCOUNT_KEY: &'static str = "STORAGE_ACCOUNT_KEY";
-static QUEUE_NAME:  &'static str = "queue_name";
-static QUEUE_URL:  &'static str = "https://my-storage-account-name.queue.core.windows.net/queue_name/messages";
-static X_MS_VERSION: &'static str = "2011-08-18";
+/// how long azure accepts a request after the time it was signed with, either way. Past that it's a 403.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key#specifying-the-date-header
+pub const SIGNATURE_VALIDITY_SECONDS: i64 = 15 * 60;
+
+/// a request that's been built and signed but not sent, for when something else does the sending (your own
+/// dispatcher, a batching layer) or to look at exactly what would go over the wire.
+/// the signature covers the date it was made with, so it's only good for `SIGNATURE_VALIDITY_SECONDS` after
+/// being built and `into_request` refuses it after that. There's no re-signing one in place, call the
+/// `build_*_request` method again for a fresh one (which is also what a retry needs).
+#[derive(Debug)]
+pub struct SignedRequest {
+    request: reqwest::Request,
+    valid_until: DateTime<Utc>,
+}
+
+impl SignedRequest {
+    /// the request as it would be sent, to look at but not change. Changing a signed header breaks the
+    /// signature.
+    pub fn request(&self) -> &reqwest::Request {
+        &self.request
+    }
+
+    /// when azure stops accepting it, by this machine's clock.
+    pub fn valid_until(&self) -> DateTime<Utc> {
+        self.valid_until
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Utc::now() >= self.valid_until
+    }
+
+    /// the request to hand to `reqwest::Client::execute`, as long as there's still time left on it. Send it
+    /// soon after, it has to arrive before `valid_until` too.
+    pub fn into_request(self) -> Result<reqwest::Request, QueueError> {
+        match self.is_expired() {
+            true => Err(QueueError::InvalidConfig(format!(
+                "signed request expired at {}, build it again for a new signature",
+                self.valid_until.to_rfc3339()
+            ))),
+            false => Ok(self.request),
+        }
+    }
+}
+
+/// what `QueueClient::dry_run` signed, and how. `string_to_sign` and `authorization` are `None` for a
+/// credential that doesn't sign (SAS), and `authorization` for a bearer token is the token, so don't log it.
+/// `canonicalized_headers` and `canonicalized_resource` are the shared key ones, whichever credential it is.
+#[derive(Debug)]
+pub struct SigningDetails {
+    /// exactly what was hmac'd, newlines and all
+    pub string_to_sign: Option<String>,
+    /// the `Authorization` header, e.g. `SharedKey myaccount:ctzMq410TV3wS7upTBcunJTDLEJwMAZuFPfr0mrrA08=`
+    pub authorization: Option<String>,
+    pub canonicalized_headers: String,
+    pub canonicalized_resource: String,
+    /// the request that would have been sent
+    pub request: reqwest::Request,
+}
+
+/// the x-ms-version a client sends unless told otherwise. It used to be 2011-08-18, which is too old for
+/// a ttl of -1, bearer tokens and the newer error formats. Set it back with the builder's `.api_version()` if
+/// something you talk to only knows the old one.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/versioning-for-the-azure-storage-services
+pub const DEFAULT_API_VERSION: &str = "2021-12-02";
+
+/// storage service versions the queue service has shipped. Anything else that looks like a version is still
+/// sent, with a warning logged.
+pub const KNOWN_API_VERSIONS: &[&str] = &[
+    "2009-09-19", "2011-08-18", "2012-02-12", "2013-08-15", "2014-02-14", "2015-02-21", "2015-04-05",
+    "2015-07-08", "2015-12-11", "2016-05-31", "2017-04-17", "2017-07-29", "2017-11-09", "2018-03-28",
+    "2018-11-09", "2019-02-02", "2019-07-07", "2019-10-10", "2019-12-12", "2020-02-10", "2020-04-08",
+    "2020-06-12", "2020-08-04", "2020-10-02", "2020-12-06", "2021-02-12", "2021-04-10", "2021-06-08",
+    "2021-08-06", "2021-10-04", "2021-12-02", "2022-11-02", "2023-01-03", "2023-05-03", "2023-08-03",
+    "2023-11-03", "2024-05-04", "2024-08-04", "2024-11-04", "2025-01-05",
+];
 
 
 /// we can't use chrono's `%Z` format here as the api does not allow UTC as a timezone.
-/// I hardcode it to GMT but switch your timezone about as you see fit, it will throw an 'invalid time' response
-/// if it doesn't like the format.  The format is allegedly RFC1123 but the documentation for the dotnet parser
+/// I hardcode it to GMT, which is only true if the time is actually UTC - so whatever timezone you hand this it
+/// gets converted first. Stamping local time with GMT gets you a date hours off from what azure expects and
+/// intermittent 403s if you're anywhere other than UTC.
+/// The format is allegedly RFC1123 but the documentation for the dotnet parser
 /// which I assume is what is being used suggests their format is only 'based' on it.
 /// https://learn.microsoft.com/en-us/dotnet/api/system.globalization.datetimeformatinfo.rfc1123pattern?view=net-8.0
 ///
-fn format_date_str(dt: DateTime<Local>) -> String {
-    format!("{}", dt.format("%a, %d %b %Y %H:%M:%S GMT"))
+pub(crate) fn format_date_str<Tz: TimeZone>(dt: DateTime<Tz>) -> String {
+    format!("{}", dt.with_timezone(&Utc).format("%a, %d %b %Y %H:%M:%S GMT"))
 }
 
 /// the canonicalized_headers string just contains all the header values pre-pended with 'x-ms-' stuffed in the signature
-/// this is because they are matched with the values in the actual request.  For queues we only pass two so it's just a simple
-/// format string.
-/// if you have more headers the method in the unofficial azure rust sdk is going to be more sane:
+/// this is because they are matched with the values in the actual request. It used to be a format string with
+/// x-ms-date and x-ms-version in it, which was fine right up until anything else x-ms- got sent
+/// (client request ids, `x-ms-meta-*` for metadata) and the signature quietly stopped matching.
+/// Now it's built the same way as the unofficial azure rust sdk does it:
 /// https://github.com/Azure/azure-sdk-for-rust/blob/ddedf470b09c1b1ce8a7dc050aded67211b5519b/sdk/storage/src/authorization/authorization_policy.rs#L155
 ///
-fn canonical_headers(date_time: String) -> String {
-    // Time Format: "Sun, 02 Sep 2009 20:36:40 GMT"
-    // this is RFC1123 "%a, %d %b %Y %H:%M:%S %Z"
-    // https://docs.rs/chrono_parser/latest/chrono_parser/formats/constant.RFC1123.html
-    format!("x-ms-date:{}\nx-ms-version:{}", date_time, X_MS_VERSION)
-}
-
-/// construct the canonicalized_resource string according to the documentation at:
-/// https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key#constructing-the-canonicalized-resource-string
-/// note: for queues you have to append the /messages endpoint despite the documentation not suggesting that at all.
-fn canonical_resource() -> String {
-    let mut cr_string = Vec::<String>::new();
-    cr_string.push("/".to_string());
-    cr_string.push(STORAGE_ACCOUNT_NAME.to_string());
-    cr_string.push("/".to_string());
-    cr_string.push(QUEUE_NAME.to_string());
-    cr_string.push("/messages".to_string());
-    cr_string.join("")
+/// pass it every header being sent, it picks out the x-ms- ones and normalises them with `ms_header_map`.
+/// The client builds the headers it sends from that same map, so what's signed and what's sent can't drift.
+/// Headers sent as
+/// ```text
+/// x-ms-version: 2021-12-02
+/// Content-Type: application/xml
+/// X-MS-Date:   Sat, 21 Feb 2015 00:48:38 GMT
+/// x-ms-meta-tags: a
+/// x-ms-meta-tags: b
+/// ```
+/// come out as
+/// ```text
+/// x-ms-date:Sat, 21 Feb 2015 00:48:38 GMT
+/// x-ms-meta-tags:a,b
+/// x-ms-version:2021-12-02
+/// ```
+/// with no newline after the last one, `construct_signature` puts the one before the resource in.
+pub(crate) fn canonical_headers(headers: &[(String, String)]) -> String {
+    let lines: Vec<String> =
+        ms_header_map(headers).iter().map(|(name, value)| format!("{}:{}", name, value)).collect();
+    lines.join("\n")
+}
+
+/// the x-ms- headers out of `headers`, the way the signature wants them:
+/// - names lowercased
+/// - values trimmed, with any run of whitespace (line folding included) squashed to one space
+/// - the same header given twice becomes one, values comma separated in the order they came
+/// - sorted by name, a BTreeMap sorts by the whole name so x-ms-date comes before x-ms-date-foo
+///
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key#constructing-the-canonicalized-headers-string
+pub(crate) fn ms_header_map(headers: &[(String, String)]) -> BTreeMap<String, String> {
+    let mut map = BTreeMap::<String, String>::new();
+    for (name, value) in headers {
+        let name = name.trim().to_lowercase();
+        if !name.starts_with("x-ms-") {
+            continue;
+        }
+        let value = value.split_whitespace().collect::<Vec<&str>>().join(" ");
+        map.entry(name)
+            .and_modify(|existing| {
+                existing.push(',');
+                existing.push_str(&value);
+            })
+            .or_insert(value);
+    }
+    map
+}
+
+/// base64 of the md5 of the body, for the `Content-MD5` header
+pub(crate) fn content_md5(body: &[u8]) -> String {
+    general_purpose::STANDARD.encode(Md5::digest(body))
+}
+
+/// construct the signed signature string
+/// Azure decrypts this with the shared key then compares the contents to
+/// it's computed version of the request details.  If they match it's
+/// considered to be authorized
+/// the key is already base64 decoded, that happens once when the credential is made.
+pub(crate) fn hmac_256(data: &str, secret: &[u8]) -> Result<String, QueueError> {
+    let mut hm256 = Hmac::<Sha256>::new_from_slice(secret).map_err(|_| QueueError::Hmac)?;
+    hm256.update(data.as_bytes());
+    let sig = hm256.finalize().into_bytes();
+    Ok(general_purpose::STANDARD.encode(sig))
 }
 
 /// construct_signature makes the following signature string.
 /// of note - only Content-Length is acutally parsed for queue service
-/// Date is optional - but you have to provide x-ms-date in the signature and the request regardless
-/// so it's basically not required.
+/// Date is optional - you have to provide x-ms-date in the signature and the request, unless you send a Date
+/// header instead (`ClientOptions::date_header`), in which case it goes here and x-ms-date goes nowhere.
 ///
 /// StringToSign = VERB + "\n" +
 ///                Content-Encoding + "\n" +
@@ -67,128 +278,1293 @@ fn canonical_resource() -> String {
 ///                Range + "\n" +
 ///                CanonicalizedHeaders +
 ///                CanonicalizedResource;
-fn construct_signature(content_length: usize, date_time: String) -> String {
+///
+/// each operation passes its own verb and canonicalized resource (see `QueueClient::canonical_resource`), so this
+/// is the same for everything from put message to delete queue. The header slots come from `SignedHeaders`
+/// so filling in another one (Content-MD5, If-Match) doesn't mean changing every caller again.
+pub(crate) fn construct_signature(
+    verb: &str,
+    signed_headers: &SignedHeaders,
+    canonicalised_headers: String,
+    canonical_resource: String,
+) -> String {
     let mut auth_string = Vec::<String>::new();
     //verb
-    auth_string.push(String::from("POST\n"));
+    auth_string.push(format!("{}\n", verb));
     //content encoding
-    auth_string.push(String::from("\n"));
+    auth_string.push(slot(&signed_headers.content_encoding));
     //content language
-    auth_string.push(String::from("\n"));
+    auth_string.push(slot(&signed_headers.content_language));
     //content length. Must be nothing if 0
-    match content_length {
+    match signed_headers.content_length {
         0 => auth_string.push(String::from("\n")),
-        _ => auth_string.push(format!("{}\n", content_length))
+        content_length => auth_string.push(format!("{}\n", content_length))
     }
     // content-md5
-    auth_string.push(String::from("\n"));
+    auth_string.push(slot(&signed_headers.content_md5));
     //content-type (this _should_ be empty i think)
-    auth_string.push(String::from("\n"));
-    //Date
-    auth_string.push(String::from("\n"));
+    auth_string.push(slot(&signed_headers.content_type));
+    //Date. empty unless the time is going in a Date header instead of x-ms-date (x-ms-date wins otherwise)
+    auth_string.push(slot(&signed_headers.date));
     // if-modified
-    auth_string.push(String::from("\n"));
+    auth_string.push(slot(&signed_headers.if_modified_since));
     // if match
-    auth_string.push(String::from("\n"));
+    auth_string.push(slot(&signed_headers.if_match));
     // if none match
-    auth_string.push(String::from("\n"));
+    auth_string.push(slot(&signed_headers.if_none_match));
     // if unmodified since
-    auth_string.push(String::from("\n"));
+    auth_string.push(slot(&signed_headers.if_unmodified_since));
     // range
-    auth_string.push(String::from("\n"));
+    auth_string.push(slot(&signed_headers.range));
 
-    let canonicalised_headers = canonical_headers(date_time);
     auth_string.push(canonicalised_headers);
     auth_string.push(String::from("\n"));
 
-    let canonicalised_resource = canonical_resource();
-    auth_string.push(canonicalised_resource);
+    auth_string.push(canonical_resource);
 
     auth_string.join("")
 }
 
-/// the queue message is actually XML (no, I don't know why when every other azure service consumes JSON)
-/// The XML format is simple and static so we construct it manually rather than using `serde_xml_rs` or another
-/// sane XML parsing crate.
-fn create_content_string(contents: String) -> String {
-    let mut content_string = Vec::<String>::new();
-    content_string.push("<QueueMessage>\n".to_string());
-    content_string.push(format!("<MessageText>{}</MessageText>\n", contents));
-    content_string.push("</QueueMessage>".to_string());
-    content_string.join("")
+/// the standard (non x-ms-) headers that go in a shared key string to sign, by slot. Anything the request
+/// doesn't send stays `None` and its line is left empty.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SignedHeaders {
+    pub content_encoding: Option<String>,
+    pub content_language: Option<String>,
+    pub content_length: usize,
+    pub content_md5: Option<String>,
+    pub content_type: Option<String>,
+    pub date: Option<String>,
+    pub if_modified_since: Option<String>,
+    pub if_match: Option<String>,
+    pub if_none_match: Option<String>,
+    pub if_unmodified_since: Option<String>,
+    pub range: Option<String>,
 }
 
-/// construct the signed signature string
-/// Azure decrypts this with the shared key then compares the contents to
-/// it's computed version of the request details.  If they match it's
-/// considered to be authorized
-fn hmac_256(data: &str, secret: &str) -> Result<String, Error> {
-    // this is the new format for base64::decode - old way is deprecated
-    let key = general_purpose::STANDARD.decode(secret);
-    match key {
-        Ok(decoded) => {
-            let hmac = Hmac::<Sha256>::new_from_slice(&decoded);
-            match hmac {
-                Ok(mut hm256) => {
-                    hm256.update(data.as_bytes());
-                    let sig = hm256.finalize().into_bytes();
-                    Ok(general_purpose::STANDARD.encode(sig))
+impl SignedHeaders {
+    /// the slots filled from the standard headers a request sends, matched by name whatever the case.
+    /// anything in `headers` without a slot (x-ms- ones, Authorization, Host...) is ignored here.
+    pub(crate) fn new(content_length: usize, date: Option<String>, headers: &[(String, String)]) -> SignedHeaders {
+        let header = |name: &str| {
+            headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.clone())
+        };
+        SignedHeaders {
+            content_encoding: header("Content-Encoding"),
+            content_language: header("Content-Language"),
+            content_length,
+            content_md5: header("Content-MD5"),
+            content_type: header("Content-Type"),
+            date,
+            if_modified_since: header("If-Modified-Since"),
+            if_match: header("If-Match"),
+            if_none_match: header("If-None-Match"),
+            if_unmodified_since: header("If-Unmodified-Since"),
+            range: header("Range"),
+        }
+    }
+}
+
+/// one line of the string to sign, empty if the header isn't sent
+fn slot(value: &Option<String>) -> String {
+    format!("{}\n", value.as_deref().unwrap_or_default())
+}
+
+/// the SharedKeyLite version of `construct_signature`. Much shorter, which makes it a lot easier to eyeball
+/// when a signature won't match:
+///
+/// StringToSign = VERB + "\n" +
+///                Content-MD5 + "\n" +
+///                Content-Type + "\n" +
+///                Date + "\n" +
+///                CanonicalizedHeaders +
+///                CanonicalizedResource;
+///
+/// the canonicalized headers are the same as shared key, the resource isn't, see `canonical_resource_lite`.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key#shared-key-lite-and-table-service-format-for-2009-09-19-and-later
+pub(crate) fn construct_lite_signature(
+    verb: &str,
+    signed_headers: &SignedHeaders,
+    canonicalised_headers: String,
+    canonical_resource: String,
+) -> String {
+    let mut auth_string = Vec::<String>::new();
+    auth_string.push(format!("{}\n", verb));
+    auth_string.push(slot(&signed_headers.content_md5));
+    auth_string.push(slot(&signed_headers.content_type));
+    // date, filled in the same cases as in construct_signature
+    auth_string.push(slot(&signed_headers.date));
+    auth_string.push(canonicalised_headers);
+    auth_string.push(String::from("\n"));
+    auth_string.push(canonical_resource);
+    auth_string.join("")
+}
+
+/// lite only puts `comp` from the query on the resource, as `?comp=value`, and nothing else.
+pub(crate) fn canonical_resource_lite(resource_path: &str, query: &[(String, String)]) -> String {
+    match query.iter().find(|(name, _)| name.eq_ignore_ascii_case("comp")) {
+        Some((_, comp)) => format!("{}?comp={}", resource_path, comp),
+        None => resource_path.to_string(),
+    }
+}
+
+/// the query parameter part of the canonicalized resource: `\nname:value` per parameter, names lowercased and
+/// sorted, and a parameter given more than once gets one line with its values sorted and comma separated.
+/// Values are the decoded ones, not what's url encoded on the wire. The example from the docs,
+/// `?restype=container&comp=list&include=snapshots&include=metadata&include=uncommittedblobs`, comes out as:
+/// ```text
+///
+/// comp:list
+/// include:metadata,snapshots,uncommittedblobs
+/// restype:container
+/// ```
+/// and for queues, get messages with a server timeout is `\nnumofmessages:3\ntimeout:5\nvisibilitytimeout:40`.
+/// get the order wrong and every request with two or more parameters is a 403.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key#shared-key-format-for-2009-09-19-and-later
+pub(crate) fn canonical_query(query: &[(&str, String)]) -> String {
+    let mut params = BTreeMap::<String, Vec<&str>>::new();
+    for (name, value) in query {
+        params.entry(name.to_lowercase()).or_default().push(value);
+    }
+    let mut lines = Vec::<String>::new();
+    for (name, mut values) in params {
+        values.sort();
+        lines.push(format!("\n{}:{}", name, values.join(",")));
+    }
+    lines.join("")
+}
+
+/// unset and empty are treated the same, docker compose in particular loves to hand you empty variables.
+pub(crate) fn optional_env(name: &str) -> Result<Option<String>, QueueError> {
+    match std::env::var(name) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(_)) => Err(QueueError::InvalidConfig(format!(
+            "environment variable {} is not valid unicode",
+            name
+        ))),
+    }
+}
+
+pub(crate) fn required_env(name: &str) -> Result<String, QueueError> {
+    optional_env(name)?.ok_or_else(|| missing_env(name))
+}
+
+fn missing_env(name: &str) -> QueueError {
+    QueueError::InvalidConfig(format!("environment variable {} is not set", name))
+}
+
+/// azurite and the old storage emulator use path style urls, `http://127.0.0.1:10001/devstoreaccount1/queue`,
+/// rather than `https://account.queue.core.windows.net/queue`. We spot those by the endpoint's path being
+/// exactly the account name.
+/// https://learn.microsoft.com/en-us/azure/storage/common/storage-use-azurite#differences-between-azurite-and-azure-storage
+fn is_path_style(account_name: &str, queue_endpoint: &str) -> bool {
+    let without_scheme = match queue_endpoint.split_once("://") {
+        Some((_, rest)) => rest,
+        None => queue_endpoint,
+    };
+    match without_scheme.split_once('/') {
+        Some((_, path)) => path.trim_end_matches('/') == account_name,
+        None => false,
+    }
+}
+
+/// A client bound to a single queue in a single storage account.
+/// Everything that used to be a hardcoded static lives here now, so you can create as many of these as you like
+/// (one per account/queue) and share them between tokio tasks - it's just owned strings and a reqwest handle
+/// so it's `Send + Sync` and cheap enough to clone. If you've got lots of queues in one account get them from a
+/// `QueueServiceClient` so they share credentials and a connection pool.
+#[derive(Clone, Debug)]
+pub struct QueueClient {
+    account_name: String,
+    credential: Arc<dyn Credential>,
+    queue_name: String,
+    queue_endpoint: String,
+    api_version: String,
+    /// the emulator puts the account name in the url path instead of the host name, see `is_path_style`
+    path_style: bool,
+    message_encoding: MessageEncoding,
+    /// applied to every put that doesn't say otherwise, `None` leaves it to azure (7 days / visible straight away)
+    default_message_ttl: Option<i64>,
+    default_visibility_timeout: Option<u32>,
+    options: ClientOptions,
+    /// reqwest clients are a handle onto a connection pool, so cloning this shares the pool rather than
+    /// making a new one.
+    http: reqwest::Client,
+    /// `http` was handed in, or has a proxy or tls settings, so a `QueuePool` has to leave it alone
+    own_transport: bool,
+    /// see `clock_skew`. Shared between clones, they're all on the same clock.
+    clock_skew_ms: Arc<AtomicI64>,
+    client_request_id: Option<String>,
+    /// where the time that gets signed into each request comes from. Always `Utc::now`, it's only here so a
+    /// test can see that every attempt is signed with the time it's sent at.
+    now: fn() -> DateTime<Utc>,
+}
+
+impl QueueClient {
+    /// `account_key` is the base64 key from the portal (Access keys blade). It's checked here, a key that
+    /// doesn't decode is a `QueueError::InvalidAccountKey` before anything goes over the network.
+    /// The queue url is derived from the account and queue names for the public azure cloud,
+    /// use the builder's `.cloud()` if you're somewhere else.
+    pub fn new(account_name: &str, account_key: &str, queue_name: &str) -> Result<QueueClient, QueueError> {
+        let queue_endpoint = CloudEnvironment::Public.queue_endpoint(account_name);
+        let credential = SharedKeyCredential::new(account_name, account_key)?;
+        let http = ClientOptions::default().http_client()?;
+        Ok(QueueClient::from_parts(account_name, Arc::new(credential), queue_name, &queue_endpoint, http))
+    }
+
+    /// build a client from a storage connection string, the thing you get from the portal or your ops team:
+    /// `DefaultEndpointsProtocol=https;AccountName=...;AccountKey=...;EndpointSuffix=core.windows.net`
+    /// `QueueEndpoint=` overrides the url built from the account name, and `UseDevelopmentStorage=true`
+    /// points at the local emulator with its well known account.
+    /// a missing AccountName/AccountKey or a queue name azure won't accept is a `QueueError::InvalidConfig`
+    /// saying what's wrong.
+    pub fn from_connection_string(conn_str: &str, queue_name: &str) -> Result<QueueClient, QueueError> {
+        let parsed = connection_string::parse_connection_string(conn_str)?;
+        builder::validate_queue_name(queue_name)?;
+        Ok(QueueClient::from_parts(
+            &parsed.account_name,
+            Arc::new(SharedKeyCredential::new(&parsed.account_name, &parsed.account_key)?),
+            queue_name,
+            &parsed.queue_endpoint,
+            ClientOptions::default().http_client()?,
+        ))
+    }
+
+    /// a client for `queue_name` on a local azurite / storage emulator, `http://127.0.0.1:10001/devstoreaccount1`
+    /// with the well known account and key, so you can try things out without an azure account:
+    /// `docker run -p 10001:10001 mcr.microsoft.com/azure-storage/azurite azurite-queue --queueHost 0.0.0.0`
+    /// use `QueueClientBuilder::new().emulator().endpoint(..)` if it's somewhere else.
+    /// https://learn.microsoft.com/en-us/azure/storage/common/storage-use-azurite
+    pub fn emulator(queue_name: &str) -> Result<QueueClient, QueueError> {
+        Ok(QueueClientBuilder::new().emulator().queue_name(queue_name).build()?)
+    }
+
+    /// build a client purely from the environment, for containers and the like.
+    /// `AZURE_QUEUE_NAME` is always required. The account comes from either `AZURE_STORAGE_CONNECTION_STRING`
+    /// or `AZURE_STORAGE_ACCOUNT` + `AZURE_STORAGE_KEY` (+ optionally `AZURE_STORAGE_ENDPOINT_SUFFIX`).
+    /// The connection string takes precedence; if both are set and name a different account or key we refuse
+    /// rather than guess which one you meant.
+    pub fn from_env() -> Result<QueueClient, QueueError> {
+        let queue_name = required_env("AZURE_QUEUE_NAME")?;
+        builder::validate_queue_name(&queue_name)?;
+        let account_name = optional_env("AZURE_STORAGE_ACCOUNT")?;
+        let account_key = optional_env("AZURE_STORAGE_KEY")?;
+
+        if let Some(conn_str) = optional_env("AZURE_STORAGE_CONNECTION_STRING")? {
+            let parsed = connection_string::parse_connection_string(&conn_str)?;
+            if account_name.as_ref().is_some_and(|name| *name != parsed.account_name) {
+                return Err(QueueError::InvalidConfig(
+                    "AZURE_STORAGE_CONNECTION_STRING and AZURE_STORAGE_ACCOUNT name different accounts. \
+                     The connection string takes precedence, unset one of them"
+                        .to_string(),
+                ));
+            }
+            if account_key.as_ref().is_some_and(|key| *key != parsed.account_key) {
+                return Err(QueueError::InvalidConfig(
+                    "AZURE_STORAGE_CONNECTION_STRING and AZURE_STORAGE_KEY contain different keys. \
+                     The connection string takes precedence, unset one of them"
+                        .to_string(),
+                ));
+            }
+            return Ok(QueueClient::from_parts(
+                &parsed.account_name,
+                Arc::new(SharedKeyCredential::new(&parsed.account_name, &parsed.account_key)?),
+                &queue_name,
+                &parsed.queue_endpoint,
+                ClientOptions::default().http_client()?,
+            ));
+        }
+
+        let account_name = account_name.ok_or_else(|| missing_env("AZURE_STORAGE_ACCOUNT"))?;
+        let account_key = account_key.ok_or_else(|| missing_env("AZURE_STORAGE_KEY"))?;
+        let cloud = match optional_env("AZURE_STORAGE_ENDPOINT_SUFFIX")? {
+            Some(suffix) => CloudEnvironment::Custom(suffix),
+            None => CloudEnvironment::Public,
+        };
+        let queue_endpoint = cloud.queue_endpoint(&account_name);
+        Ok(QueueClient::from_parts(
+            &account_name,
+            Arc::new(SharedKeyCredential::new(&account_name, &account_key)?),
+            &queue_name,
+            &queue_endpoint,
+            ClientOptions::default().http_client()?,
+        ))
+    }
+
+    /// `queue_endpoint` is the base url of the queue service (no trailing slash), the queue name and /messages
+    /// get stuck on the end of it. `http` is taken as given so clients can share one connection pool, building
+    /// a reqwest client isn't cheap.
+    pub(crate) fn from_parts(
+        account_name: &str,
+        credential: Arc<dyn Credential>,
+        queue_name: &str,
+        queue_endpoint: &str,
+        http: reqwest::Client,
+    ) -> QueueClient {
+        QueueClient {
+            account_name: account_name.to_string(),
+            credential,
+            queue_name: queue_name.to_string(),
+            queue_endpoint: queue_endpoint.to_string(),
+            api_version: DEFAULT_API_VERSION.to_string(),
+            path_style: is_path_style(account_name, queue_endpoint),
+            message_encoding: MessageEncoding::default(),
+            default_message_ttl: None,
+            default_visibility_timeout: None,
+            http,
+            own_transport: false,
+            options: ClientOptions::default(),
+            clock_skew_ms: Arc::new(AtomicI64::new(0)),
+            client_request_id: None,
+            now: Utc::now,
+        }
+    }
+
+    /// a copy of this client that sends `x-ms-client-request-id` with every request, so you can find your
+    /// requests in storage analytics logs by your own id:
+    /// `client.with_client_request_id("order-1234")?.send_message(..)`
+    /// azure logs it next to its own `x-ms-request-id`. Up to 1024 visible ascii characters.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/common-rest-api-headers
+    pub fn with_client_request_id(&self, client_request_id: &str) -> Result<QueueClient, QueueError> {
+        if client_request_id.is_empty()
+            || client_request_id.len() > 1024
+            || !client_request_id.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+        {
+            return Err(QueueError::InvalidConfig(format!(
+                "client request id must be 1 to 1024 visible ascii characters, got '{}'",
+                client_request_id
+            )));
+        }
+        let mut client = self.clone();
+        client.client_request_id = Some(client_request_id.to_string());
+        Ok(client)
+    }
+
+    /// a copy of this client that asks azure to spend at most `timeout` seconds (1-30) on each request, e.g.
+    /// `client.with_server_timeout(5)?.get_messages(..)`. See `ClientOptions::server_timeout`.
+    pub fn with_server_timeout(&self, timeout: u32) -> Result<QueueClient, QueueError> {
+        builder::validate_server_timeout(timeout)?;
+        let mut client = self.clone();
+        client.options.server_timeout = Some(timeout);
+        Ok(client)
+    }
+
+    /// how far this machine's clock is behind the storage service's (negative if it's ahead), as worked out
+    /// the last time azure turned a request down for its x-ms-date. Zero until that happens. Worth alerting on,
+    /// it means the clock needs fixing even though requests are going through again.
+    pub fn clock_skew(&self) -> chrono::Duration {
+        chrono::Duration::milliseconds(self.clock_skew_ms.load(Ordering::Relaxed))
+    }
+
+    /// sign everything from now on with a new account key, without building a new client and losing its
+    /// warm connections. Requests already in flight finish with the old key. Every clone of this client (and
+    /// anything made with `with_api_version` etc.) shares the credential, so they all switch over.
+    /// errors if the key doesn't decode, or if this client isn't using an account key at all.
+    pub fn set_account_key(&self, account_key: &str) -> Result<(), QueueError> {
+        self.credential.set_account_key(account_key)
+    }
+
+    /// the same as `set_account_key` for a client using a SAS token.
+    pub fn set_sas_token(&self, sas_token: &str) -> Result<(), QueueError> {
+        self.credential.set_sas_token(sas_token)
+    }
+
+    pub fn account_name(&self) -> &str {
+        &self.account_name
+    }
+
+    pub fn queue_name(&self) -> &str {
+        &self.queue_name
+    }
+
+    /// where messages get posted to, `{endpoint}/{queue}/messages`
+    pub fn queue_url(&self) -> String {
+        self.resource_url("/messages")
+    }
+
+    /// `path` is relative to the queue, e.g. `/messages` or `/messages/{id}`, or empty for the queue itself
+    fn resource_url(&self, path: &str) -> String {
+        format!("{}/{}{}", self.queue_endpoint, self.queue_name, path)
+    }
+
+    pub fn api_version(&self) -> &str {
+        &self.api_version
+    }
+
+    /// a copy of this client that sends a different x-ms-version, for a one off call or comparing how two
+    /// versions behave: `client.with_api_version("2011-08-18")?.send_message(..)`. It's cheap, the
+    /// connection pool and credential are shared. Both the header and the signature use the new version.
+    pub fn with_api_version(&self, api_version: &str) -> Result<QueueClient, QueueError> {
+        builder::validate_api_version(api_version, self.credential.min_api_version())?;
+        let mut client = self.clone();
+        client.api_version = api_version.to_string();
+        Ok(client)
+    }
+
+    pub fn message_encoding(&self) -> MessageEncoding {
+        self.message_encoding
+    }
+
+    /// construct the canonicalized_resource string according to the documentation at:
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key#constructing-the-canonicalized-resource-string
+    /// note: for queues you have to append the /messages endpoint (or whatever path below the queue you're hitting)
+    /// despite the documentation not suggesting that at all.
+    /// any query parameters go on the end, see `canonical_query`.
+    fn canonical_resource(&self, path: &str, query: &[(&str, String)]) -> String {
+        let mut cr_string = vec![self.canonical_path(path)];
+        cr_string.push(canonical_query(query));
+        cr_string.join("")
+    }
+
+    /// the canonicalized resource without any query parameters, `/{account}/{queue}{path}`
+    fn canonical_path(&self, path: &str) -> String {
+        let mut cr_string = vec![
+            "/".to_string(),
+            self.account_name.clone(),
+        ];
+        // path style urls (azurite) have the account name in the path too, and the canonicalized resource
+        // is "/" + account + the url path, so it ends up in there twice: /devstoreaccount1/devstoreaccount1/queue
+        if self.path_style {
+            cr_string.push("/".to_string());
+            cr_string.push(self.account_name.clone());
+        }
+        cr_string.extend([
+            "/".to_string(),
+            self.queue_name.clone(),
+            path.to_string(),
+        ]);
+        cr_string.join("")
+    }
+
+    /// sign and send a request against `path` below the queue url, e.g. `/messages`, retrying transient
+    /// failures as `ClientOptions` says.
+    /// `query` goes both on the url and into the canonicalized resource, so everything that's sent is signed.
+    /// anything other than a 2xx from azure comes back as `QueueError::Service` with whatever the body said,
+    /// which is normally an xml blob with an error code in it.
+    pub(crate) async fn execute(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<String>,
+    ) -> Result<QueueResponse, QueueError> {
+        self.execute_with_headers(method, path, query, &[], body).await
+    }
+
+    /// a request for anything this crate doesn't have a method for, signed and retried like the rest, with
+    /// the whole response back. `path`, `query` and `body` are as for `dry_run`, e.g. get messages is
+    /// `raw_request(Method::GET, "/messages", &[("numofmessages", "5".to_string())], None)`.
+    /// a non 2xx is still a `QueueError::Service`.
+    pub async fn raw_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<String>,
+    ) -> Result<QueueResponse, QueueError> {
+        self.execute(method, path, query, body).await
+    }
+
+    /// `execute` for operations that send headers of their own, e.g. `x-ms-meta-*` for metadata. They're
+    /// signed along with everything else: x-ms- ones in the canonicalized headers, the standard ones
+    /// (Content-Type etc.) in their own lines of the string to sign.
+    pub(crate) async fn execute_with_headers(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, String)],
+        headers: &[(String, String)],
+        body: Option<String>,
+    ) -> Result<QueueResponse, QueueError> {
+        let query = self.with_server_timeout_query(query);
+        let query = query.as_slice();
+        #[cfg(feature = "tracing")]
+        {
+            use tracing::Instrument;
+            // never the Authorization header or anything with the key in it, just enough to match up with
+            // storage analytics logs
+            let span = tracing::debug_span!(
+                "queue_request",
+                verb = %method,
+                resource = %self.canonical_resource(path, query).replace('\n', " "),
+                status = tracing::field::Empty,
+                request_id = tracing::field::Empty,
+            );
+            let result = self
+                .execute_with_retries(method, path, query, headers, body)
+                .instrument(span.clone())
+                .await;
+            let (status, headers) = match &result {
+                Ok(response) => (Some(response.status), Some(&response.headers)),
+                Err(QueueError::Service { status, headers, .. }) => (Some(*status), Some(headers)),
+                Err(_) => (None, None),
+            };
+            if let Some(status) = status {
+                span.record("status", status);
+            }
+            if let Some(request_id) = headers.and_then(|headers| headers.get("x-ms-request-id")) {
+                span.record("request_id", request_id.as_str());
+            }
+            if let Err(e) = &result {
+                span.in_scope(|| tracing::debug!(error = %e, "queue request failed"));
+            }
+            result
+        }
+        #[cfg(not(feature = "tracing"))]
+        self.execute_with_retries(method, path, query, headers, body).await
+    }
+
+    /// the server timeout is just another query parameter, signed like the rest
+    fn with_server_timeout_query<'a>(&self, query: &[(&'a str, String)]) -> Vec<(&'a str, String)> {
+        let mut query = query.to_vec();
+        if let Some(server_timeout) = self.options.server_timeout {
+            query.push(("timeout", server_timeout.to_string()));
+        }
+        query
+    }
+
+    async fn execute_with_retries(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, String)],
+        headers: &[(String, String)],
+        body: Option<String>,
+    ) -> Result<QueueResponse, QueueError> {
+        // the body is fixed as bytes here, after encoding and escaping, so there's nothing left that could make
+        // what's signed and what's sent disagree
+        let body_content = body.unwrap_or_default().into_bytes();
+        let mut attempt = 0;
+        let mut corrected_skew = false;
+        let mut reauthorized = false;
+        // only kept with debug_auth on, see `ClientOptions::debug_auth`
+        let mut string_to_sign = None;
+        loop {
+            attempt += 1;
+            // every attempt is signed from scratch, a retry with the old x-ms-date would eventually fall outside
+            // the allowed clock skew
+            let sent = self.send_once(method.clone(), path, query, headers, body_content.clone(), &mut string_to_sign);
+            match sent.await {
+                // one go with the server's idea of the time, which doesn't count as a retry
+                Err(e) if !corrected_skew && self.credential.sends_date() && self.correct_clock_skew(&e) => {
+                    corrected_skew = true;
+                    attempt -= 1;
                 }
-                Err(e) => {
-                    // we can't do anything if we can't encrypt the sig, so panic
-                    // hopefully with a useful error
-                    panic!("Couldn't Create hmac instance{:?}",  e);
+                // the credential had something stale (a rotated SAS token), it gets one go with a fresh one
+                Err(e)
+                    if !reauthorized
+                        && e.error_code() == Some("AuthenticationFailed")
+                        && self.credential.authentication_failed() =>
+                {
+                    reauthorized = true;
+                    attempt -= 1;
                 }
+                Err(e) if is_transient(&e) && attempt <= self.options.retry.max_retries => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(attempt, error = %e, "retrying queue request");
+                    tokio::time::sleep(self.options.retry.delay(attempt, retry_after(&e))).await;
+                }
+                Err(e) => return Err(debug_auth::explain(e, string_to_sign)),
+                result => return result,
             }
+        }
+    }
 
+    /// every x-ms- header this request sends, already through `ms_header_map`. These exact pairs get both
+    /// signed and sent. Anything x-ms- a particular operation needs (`x-ms-meta-*`) goes in `extra`.
+    /// `date` is None when the credential doesn't want x-ms-date (SAS).
+    fn ms_headers(&self, date: Option<&str>, extra: &[(String, String)]) -> Vec<(String, String)> {
+        // Time Format: "Sun, 02 Sep 2009 20:36:40 GMT"
+        // this is RFC1123 "%a, %d %b %Y %H:%M:%S %Z"
+        // https://docs.rs/chrono_parser/latest/chrono_parser/formats/constant.RFC1123.html
+        let mut headers = vec![("x-ms-version".to_string(), self.api_version.clone())];
+        if let Some(date) = date {
+            headers.push(("x-ms-date".to_string(), date.to_string()));
         }
-        Err(_) => panic!("Couldn't Decode account key to base64")
+        if let Some(client_request_id) = &self.client_request_id {
+            headers.push(("x-ms-client-request-id".to_string(), client_request_id.clone()));
+        }
+        headers.extend_from_slice(extra);
+        ms_header_map(&headers).into_iter().collect()
+    }
+
+    /// if azure refused the request because our x-ms-date was too far out, remember how far out from its Date
+    /// header and say so. Azure allows 15 minutes either way, so this only kicks in when the clock is way off.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key#specifying-the-date-header
+    fn correct_clock_skew(&self, error: &QueueError) -> bool {
+        let (headers, body) = match error {
+            QueueError::Service { status: 403, headers, body } => (headers, body),
+            _ => return false,
+        };
+        // e.g. <AuthenticationErrorDetail>Request date header too old: 'Fri, 09 Oct 2009 21:04:30 GMT'</AuthenticationErrorDetail>
+        // not just "date", a signature mismatch quotes azure's string to sign and that has x-ms-date in it
+        let date_problem = xml::element(body, "AuthenticationErrorDetail")
+            .is_some_and(|detail| detail.trim_start().to_ascii_lowercase().starts_with("request date header"));
+        let server_time = headers
+            .get("date")
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+            .map(|date| date.with_timezone(&Utc));
+        match (date_problem, server_time) {
+            (true, Some(server_time)) => {
+                // against the same clock that dates the requests, or the correction is off by the difference
+                let skew = server_time - (self.now)();
+                log::warn!(
+                    "the storage service's clock is {}s off ours, adjusting x-ms-date to match",
+                    skew.num_seconds()
+                );
+                self.clock_skew_ms.store(skew.num_milliseconds(), Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    async fn send_once(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, String)],
+        headers: &[(String, String)],
+        body_content: Vec<u8>,
+        string_to_sign: &mut Option<String>,
+    ) -> Result<QueueResponse, QueueError> {
+        let request = self.build_request(method, path, query, headers, body_content, string_to_sign).await?;
+        let response = self.http.execute(request).await?;
+
+        // OK is 201 in azure. thanks azure.
+        let status = response.status().as_u16();
+        let headers: HashMap<String, String> = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = response.bytes().await?;
+        match (200..300).contains(&status) {
+            true => Ok(QueueResponse {
+                status,
+                request_id: headers.get("x-ms-request-id").cloned(),
+                headers,
+                body,
+            }),
+            false => Err(QueueError::Service {
+                status,
+                headers,
+                body: String::from_utf8_lossy(&body).into_owned(),
+            }),
+        }
+    }
+
+    /// everything up to sending: the date, the signature and every header, ready for `http.execute`.
+    /// `query` has to already have the server timeout in it if there is one.
+    async fn build_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, String)],
+        headers: &[(String, String)],
+        body_content: Vec<u8>,
+        string_to_sign: &mut Option<String>,
+    ) -> Result<reqwest::Request, QueueError> {
+        // bytes not characters, an emoji is one char and four bytes. The signature and the Content-Length
+        // header both use this one number, get them out of step and azure answers with an unhelpful 403.
+        let content_length = body_content.len();
+        // see notes on this function for silliness. The skew goes in here so the header and the signature
+        // both get the corrected time.
+        let dt = format_date_str((self.now)() + self.clock_skew());
+        // the time goes in one of x-ms-date or Date, never both
+        let date_header = self.credential.sends_date() && self.options.date_header;
+        let ms_date = (self.credential.sends_date() && !date_header).then_some(dt.as_str());
+        let mut headers = headers.to_vec();
+        if self.options.verify_content_md5 && !body_content.is_empty() {
+            headers.push(("Content-MD5".to_string(), content_md5(&body_content)));
+        }
+        let headers = headers.as_slice();
+
+        let parts = RequestParts {
+            method: method.as_str(),
+            account_name: &self.account_name,
+            content_length,
+            date: &dt,
+            date_header,
+            api_version: &self.api_version,
+            canonical_resource: self.canonical_resource(path, query),
+            resource_path: self.canonical_path(path),
+            query: query.iter().map(|(name, value)| (name.to_string(), value.clone())).collect(),
+            ms_headers: self.ms_headers(ms_date, headers),
+            headers: headers
+                .iter()
+                .filter(|(name, _)| !name.to_ascii_lowercase().starts_with("x-ms-"))
+                .cloned()
+                .collect(),
+        };
+        let authorization = self.credential.authorize(&parts).await?;
+        if self.options.debug_auth {
+            string_to_sign.clone_from(&authorization.string_to_sign);
+        }
+
+        let mut request = self
+            .http
+            .request(method.clone(), self.resource_url(path))
+            .query(query)
+            .query(&authorization.query);
+        if date_header {
+            request = request.header("Date", &dt);
+        }
+        for (name, value) in parts.ms_headers.iter().chain(&parts.headers).chain(&authorization.headers) {
+            request = request.header(name, value);
+        }
+        let request = request
+            .header("Content-Length", content_length)
+            .body(body_content) // if you forget this your request will hang indefinitely. Yes it took a while to figure that i'd missed this.
+            .build()?;
+        Ok(request)
+    }
+
+    /// a request signed now, with the server timeout on it, for `SignedRequest`. Same as what
+    /// `execute_with_headers` would send first go, just not sent.
+    pub(crate) async fn sign_for_later(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: String,
+    ) -> Result<SignedRequest, QueueError> {
+        let query = self.with_server_timeout_query(query);
+        let signed_at = Utc::now();
+        let request = self.build_request(method, path, &query, &[], body.into_bytes(), &mut None).await?;
+        Ok(SignedRequest {
+            request,
+            valid_until: signed_at + chrono::Duration::seconds(SIGNATURE_VALIDITY_SECONDS),
+        })
     }
 
+    /// sign a request the way it would be sent and say what went into the signature, without sending anything.
+    /// for putting next to the examples in azure's docs, or another sdk's output, when a signature won't match.
+    /// `path` is relative to the queue url (`/messages`, `/messages/{id}`, or `""` for the queue itself) and
+    /// `query` is the unencoded parameters, the server timeout is added like it would be for real. `body` is
+    /// the request body exactly, so for a put message that's the `<QueueMessage>` xml, not the text.
+    pub async fn dry_run(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<String>,
+    ) -> Result<SigningDetails, QueueError> {
+        let query = self.with_server_timeout_query(query);
+        // the string to sign is only kept with debug_auth on
+        let mut client = self.clone();
+        client.options.debug_auth = true;
+        let mut string_to_sign = None;
+        let body = body.unwrap_or_default().into_bytes();
+        let request = client.build_request(method, path, &query, &[], body, &mut string_to_sign).await?;
+        let headers: Vec<(String, String)> = request
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let authorization = headers.iter().find(|(name, _)| name == "authorization").map(|(_, value)| value.clone());
+        Ok(SigningDetails {
+            string_to_sign,
+            authorization,
+            canonicalized_headers: canonical_headers(&headers),
+            canonicalized_resource: self.canonical_resource(path, &query),
+            request,
+        })
+    }
 }
 
+/// `Retry-After` on a throttled response, in seconds. It's allowed to be an http date too, azure doesn't send
+/// those but it's cheap to cope.
+fn retry_after(error: &QueueError) -> Option<Duration> {
+    let value = match error {
+        QueueError::Service { headers, .. } => headers.get("retry-after")?.trim(),
+        _ => return None,
+    };
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => {
+            let at = DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc);
+            (at - Utc::now()).to_std().ok()
+        }
+    }
+}
 
-pub async fn create_request(message_text: String) {
+/// worth another go: azure being busy (500 / 503 ServerBusy) or not reaching it at all.
+/// anything else (403, 404, 400...) will fail the same way next time.
+fn is_transient(error: &QueueError) -> bool {
+    match error {
+        QueueError::Transport(e) => e.is_connect() || e.is_timeout(),
+        QueueError::Service { status, .. } => *status == 500 || *status == 503,
+        _ => false,
+    }
+}
 
-    let body_content = create_content_string(message_text);
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
 
-    // you may have to mess with this depending on your timezone.
-    // it may be easiest to just generate utc and pretend it's GMT. see notes on this function for
-    // silliness
-    let dt = format_date_str(Local::now());
+    use super::*;
 
-    // cloning dt is lazy but we only do it once and none of this has a long lifetime.
-    let auth_str=construct_signature(body_content.len(), dt.clone());
+    static TICKS: AtomicI64 = AtomicI64::new(0);
 
-    // we panic if this doesn't work so should be ok to just unwrap this.
-    let encoded_auth = hmac_256(auth_str.as_str(), STORAGE_ACCOUNT_KEY).unwrap();
+    // a minute further on every time it's asked, x-ms-date is only to the second and the retry is straight away
+    fn ticking_clock() -> DateTime<Utc> {
+        let minutes = TICKS.fetch_add(1, Ordering::SeqCst);
+        DateTime::from_timestamp(1_700_000_000 + minutes * 60, 0).unwrap()
+    }
 
-    let auth_header = format!("SharedKey {}:{}", STORAGE_ACCOUNT_NAME, encoded_auth);
+    /// a canned response: the status line, any extra headers (each ending `\r\n`) and the body
+    pub(crate) type Reply = (&'static str, String, String);
 
-    let client = reqwest::Client::new();
-    let response = client
-        .post(QUEUE_URL)
-        .header("x-ms-date", dt)
-        .header("x-ms-version", X_MS_VERSION)
-        .header("Authorization", auth_header)
-        .header("Content-Length", body_content.len())
-        .body(body_content) // if you forget this your request will hang indefinitely. Yes it took a while to figure that i'd missed this.
-        .send()
-        .await
-        .unwrap();
-        // OK is 201 in azure. thanks azure.
-        match response.status().is_success() {
-         true => {
-            let headers = response.headers().to_owned();
-            let  body = response.bytes().await.unwrap();
-            println!("Successful Request!\nResponse Text: {:?} \nHeaders: {:?}", body, headers)
-        }
-        _ => {
-            let status = response.status();
-            println!("{:?}", status);
-            let headers = response.headers().to_owned();
-            let  body = response.bytes().await.unwrap();
-            println!("Response Text: {:?} \n Headers: {:?}", body, headers);
+    /// a pretend storage account on a random local port, for checking what actually goes over the wire. Answers
+    /// one request per reply, in order, then stops. Each request (head and body) comes out of the receiver as
+    /// it arrives, so a test can see what's been asked for so far as well as at the end.
+    /// hands back the endpoint, path style like azurite so the emulator account and key work against it.
+    pub(crate) async fn serve(replies: Vec<Reply>) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/devstoreaccount1", listener.local_addr().unwrap());
+        let (seen, requests) = mpsc::unbounded_channel::<String>();
+        tokio::spawn(async move {
+            for (status, extra_headers, body) in replies {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::<u8>::new();
+                let mut buf = [0u8; 4096];
+                // the head, then as much body as it says there is
+                let complete = |request: &[u8]| {
+                    let text = String::from_utf8_lossy(request);
+                    let (head, body) = text.split_once("\r\n\r\n")?;
+                    let length = request_header(head, "content-length").map_or(0, |length| length.parse().unwrap());
+                    (body.len() >= length).then_some(())
+                };
+                while complete(&request).is_none() {
+                    let read = socket.read(&mut buf).await.unwrap();
+                    assert!(read > 0, "connection closed before the whole request was sent");
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let _ = seen.send(String::from_utf8_lossy(&request).to_string());
+                let response = format!(
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    extra_headers,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (endpoint, requests)
+    }
+
+    /// everything `serve` was sent, once it's answered all its replies
+    pub(crate) async fn all_requests(mut requests: mpsc::UnboundedReceiver<String>) -> Vec<String> {
+        let mut all = Vec::<String>::new();
+        while let Some(request) = requests.recv().await {
+            all.push(request);
         }
+        all
+    }
+
+    /// a header from a request `serve` got, by name whatever the case
+    pub(crate) fn request_header(request: &str, name: &str) -> Option<String> {
+        let head = request.split("\r\n\r\n").next().unwrap_or_default();
+        head.lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim().to_string())
+    }
+
+    /// the SharedKey signature the service would expect for a request `serve` got, worked out from what
+    /// actually arrived rather than anything the client kept. Compare it with the Authorization header.
+    pub(crate) fn server_signature(request: &str, account_key: &str, canonical_resource: &str) -> String {
+        let head = request.split("\r\n\r\n").next().unwrap_or_default();
+        let mut lines = head.lines();
+        let verb = lines.next().unwrap().split(' ').next().unwrap();
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+            .collect();
+        let content_length = request_header(request, "content-length").map_or(0, |length| length.parse().unwrap());
+        let string_to_sign = construct_signature(
+            verb,
+            &SignedHeaders::new(content_length, request_header(request, "date"), &headers),
+            canonical_headers(&headers),
+            canonical_resource.to_string(),
+        );
+        let key = general_purpose::STANDARD.decode(account_key).unwrap();
+        hmac_256(&string_to_sign, &key).unwrap()
+    }
+
+    /// an error body like azure's when the x-ms-date is out
+    fn date_too_old() -> String {
+        "<Error><Code>AuthenticationFailed</Code><AuthenticationErrorDetail>Request date header too old: \
+         'Tue, 14 Nov 2023 22:13:20 GMT'</AuthenticationErrorDetail></Error>"
+            .to_string()
+    }
+
+    fn empty_list() -> String {
+        "<QueueMessagesList />".to_string()
+    }
+
+    #[tokio::test]
+    async fn retry_is_signed_again_with_a_new_date() {
+        let replies = vec![("503 Server Busy", String::new(), String::new()), ("200 OK", String::new(), empty_list())];
+        let (endpoint, requests) = serve(replies).await;
+        let mut client = QueueClientBuilder::new()
+            .emulator()
+            .endpoint(&endpoint)
+            .queue_name("retries")
+            .retry(RetryPolicy::new(1, Duration::from_millis(1)))
+            .build()
+            .unwrap();
+        client.now = ticking_clock;
+
+        client.get_messages(1, None).await.unwrap();
+        let seen = all_requests(requests).await;
+        assert_eq!(seen.len(), 2);
+        assert_ne!(
+            request_header(&seen[0], "x-ms-date"),
+            request_header(&seen[1], "x-ms-date"),
+            "the retry was sent with the first attempt's x-ms-date"
+        );
+        assert_ne!(
+            request_header(&seen[0], "authorization"),
+            request_header(&seen[1], "authorization"),
+            "the retry was sent with the first attempt's signature"
+        );
+    }
+
+    fn stopped_clock() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn clock_skew_is_measured_against_the_signing_clock() {
+        // azure's clock is an hour ahead of ours
+        let server_date = "Tue, 14 Nov 2023 23:13:20 GMT";
+        let replies = vec![
+            ("403 Forbidden", format!("Date: {}\r\n", server_date), date_too_old()),
+            ("200 OK", String::new(), empty_list()),
+        ];
+        let (endpoint, requests) = serve(replies).await;
+        let mut client = QueueClientBuilder::new()
+            .emulator()
+            .endpoint(&endpoint)
+            .queue_name("skewed")
+            .build()
+            .unwrap();
+        client.now = stopped_clock;
+
+        client.get_messages(1, None).await.unwrap();
+        let seen = all_requests(requests).await;
+        assert_eq!(request_header(&seen[0], "x-ms-date").unwrap(), "Tue, 14 Nov 2023 22:13:20 GMT");
+        assert_eq!(request_header(&seen[1], "x-ms-date").unwrap(), server_date);
+        assert_eq!(client.clock_skew(), chrono::Duration::hours(1));
+    }
+
+    #[test]
+    fn get_with_no_body_signs_an_empty_content_length() {
+        let headers = "x-ms-date:Tue, 14 Nov 2023 22:13:20 GMT\nx-ms-version:2021-12-02".to_string();
+        let string_to_sign = construct_signature(
+            "GET",
+            &SignedHeaders::new(0, None, &[]),
+            headers,
+            "/myaccount/myqueue/messages".to_string(),
+        );
+        assert!(string_to_sign.starts_with("GET\n"));
+        let lines: Vec<&str> = string_to_sign.split('\n').collect();
+        // verb, content encoding, content language, then content length
+        assert_eq!(lines[3], "", "a 0 content length has to be signed as an empty line, not 0");
+        assert!(!string_to_sign.contains("\n0\n"));
+    }
+
+    #[test]
+    fn dates_are_formatted_in_utc_whatever_their_offset() {
+        // 23:13:20 in UTC+2 is 21:13:20 GMT
+        let offset = chrono::FixedOffset::east_opt(2 * 3600).unwrap();
+        let local = offset.with_ymd_and_hms(2023, 11, 14, 23, 13, 20).unwrap();
+        assert_eq!(format_date_str(local), "Tue, 14 Nov 2023 21:13:20 GMT");
+        // and across midnight the day moves too
+        let behind = chrono::FixedOffset::west_opt(5 * 3600).unwrap();
+        let local = behind.with_ymd_and_hms(2023, 11, 14, 22, 0, 0).unwrap();
+        assert_eq!(format_date_str(local), "Wed, 15 Nov 2023 03:00:00 GMT");
+    }
+
+    #[tokio::test]
+    async fn sent_and_signed_api_versions_match() {
+        let client = QueueClient::emulator("versions").unwrap();
+        let older = QueueClientBuilder::new().emulator().queue_name("versions").api_version("2011-08-18").build();
+        for client in [client.clone(), client.with_api_version("2019-12-12").unwrap(), older.unwrap()] {
+            let details = client.dry_run(reqwest::Method::GET, "/messages", &[], None).await.unwrap();
+            let sent = details.request.headers()["x-ms-version"].to_str().unwrap();
+            assert_eq!(sent, client.api_version());
+            let string_to_sign = details.string_to_sign.unwrap();
+            let signed = string_to_sign.lines().find_map(|line| line.strip_prefix("x-ms-version:"));
+            assert_eq!(signed, Some(sent));
+        }
+    }
+
+    #[test]
+    fn string_to_sign_for_each_verb() {
+        let headers = "x-ms-date:Tue, 14 Nov 2023 22:13:20 GMT\nx-ms-version:2021-12-02";
+        let resource = "/myaccount/myqueue/messages";
+        let body_headers = [("Content-Type".to_string(), "application/xml".to_string())];
+        let cases = [
+            ("GET", SignedHeaders::new(0, None, &[]), "GET\n\n\n\n\n\n\n\n\n\n\n\n"),
+            ("HEAD", SignedHeaders::new(0, None, &[]), "HEAD\n\n\n\n\n\n\n\n\n\n\n\n"),
+            ("DELETE", SignedHeaders::new(0, None, &[]), "DELETE\n\n\n\n\n\n\n\n\n\n\n\n"),
+            ("PUT", SignedHeaders::new(0, None, &body_headers), "PUT\n\n\n\n\napplication/xml\n\n\n\n\n\n\n"),
+            ("POST", SignedHeaders::new(78, None, &body_headers), "POST\n\n\n78\n\napplication/xml\n\n\n\n\n\n\n"),
+        ];
+        for (verb, signed_headers, slots) in cases {
+            assert_eq!(
+                construct_signature(verb, &signed_headers, headers.to_string(), resource.to_string()),
+                format!("{}{}\n{}", slots, headers, resource),
+                "{}",
+                verb
+            );
+        }
+    }
+
+    #[test]
+    fn query_parameters_are_lowercased_sorted_and_repeats_joined() {
+        let query = [
+            ("VisibilityTimeout", "30".to_string()),
+            ("numofmessages", "32".to_string()),
+            ("include", "metadata".to_string()),
+            ("comp", "list".to_string()),
+            ("Include", "acl".to_string()),
+        ];
+        assert_eq!(
+            canonical_query(&query),
+            "\ncomp:list\ninclude:acl,metadata\nnumofmessages:32\nvisibilitytimeout:30"
+        );
+        assert_eq!(canonical_query(&[]), "");
+    }
+
+    #[tokio::test]
+    async fn put_message_query_is_signed() {
+        let client = QueueClient::new("myaccount", connection_string::EMULATOR_ACCOUNT_KEY, "myqueue").unwrap();
+        let query = [("visibilitytimeout", "5".to_string()), ("messagettl", "-1".to_string())];
+        let details = client.dry_run(reqwest::Method::POST, "/messages", &query, None).await.unwrap();
+        let resource = "/myaccount/myqueue/messages\nmessagettl:-1\nvisibilitytimeout:5";
+        assert_eq!(details.canonicalized_resource, resource);
+        assert!(details.string_to_sign.unwrap().ends_with(resource));
+        assert_eq!(details.request.url().query(), Some("visibilitytimeout=5&messagettl=-1"));
+    }
+
+    // the examples from
+    // https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key#shared-key-format-for-2009-09-19-and-later
+    // they're blob containers, but the resource is account + path whichever service it is
+    #[test]
+    fn canonical_resource_matches_the_docs() {
+        let key = connection_string::EMULATOR_ACCOUNT_KEY;
+        let container = QueueClient::new("myaccount", key, "mycontainer").unwrap();
+
+        // GET https://myaccount.blob.core.windows.net/mycontainer?restype=container&comp=metadata
+        let query = [("restype", "container".to_string()), ("comp", "metadata".to_string())];
+        assert_eq!(
+            container.canonical_resource("", &query),
+            "/myaccount/mycontainer\ncomp:metadata\nrestype:container"
+        );
+
+        // GET https://myaccount.blob.core.windows.net/mycontainer
+        //     ?restype=container&comp=list&include=snapshots&include=metadata&include=uncommittedblobs
+        let query = [
+            ("restype", "container".to_string()),
+            ("comp", "list".to_string()),
+            ("include", "snapshots".to_string()),
+            ("include", "metadata".to_string()),
+            ("include", "uncommittedblobs".to_string()),
+        ];
+        assert_eq!(
+            container.canonical_resource("", &query),
+            "/myaccount/mycontainer\ncomp:list\ninclude:metadata,snapshots,uncommittedblobs\nrestype:container"
+        );
+
+        // and a queue one, get messages with a server timeout
+        let queue = QueueClient::new("myaccount", key, "myqueue").unwrap();
+        let query = [
+            ("visibilitytimeout", "40".to_string()),
+            ("timeout", "5".to_string()),
+            ("numofmessages", "3".to_string()),
+        ];
+        assert_eq!(
+            queue.canonical_resource("/messages", &query),
+            "/myaccount/myqueue/messages\nnumofmessages:3\ntimeout:5\nvisibilitytimeout:40"
+        );
+        assert_eq!(queue.canonical_resource("/messages", &[]), "/myaccount/myqueue/messages");
+    }
+
+    // https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key#shared-key-lite-and-table-service-format-for-2009-09-19-and-later
+    #[test]
+    fn lite_resource_and_string_to_sign_match_the_docs() {
+        // GET https://myaccount.blob.core.windows.net/mycontainer?restype=container&comp=metadata
+        let query = vec![
+            ("restype".to_string(), "container".to_string()),
+            ("comp".to_string(), "metadata".to_string()),
+        ];
+        assert_eq!(canonical_resource_lite("/myaccount/mycontainer", &query), "/myaccount/mycontainer?comp=metadata");
+        // nothing but comp makes it in
+        let query = vec![("numofmessages".to_string(), "3".to_string())];
+        assert_eq!(canonical_resource_lite("/myaccount/myqueue/messages", &query), "/myaccount/myqueue/messages");
+
+        // the put blob example, headers as they'd be sent
+        let headers = [
+            ("x-ms-meta-m2".to_string(), "v2".to_string()),
+            ("x-ms-date".to_string(), "Sun, 20 Sep 2009 20:36:40 GMT".to_string()),
+            ("Content-Type".to_string(), "text/plain; charset=UTF-8".to_string()),
+            ("x-ms-meta-m1".to_string(), "v1".to_string()),
+        ];
+        let string_to_sign = construct_lite_signature(
+            "PUT",
+            &SignedHeaders::new(0, None, &headers),
+            canonical_headers(&headers),
+            canonical_resource_lite("/testaccount1/mycontainer/hello.txt", &[]),
+        );
+        assert_eq!(
+            string_to_sign,
+            "PUT\n\ntext/plain; charset=UTF-8\n\nx-ms-date:Sun, 20 Sep 2009 20:36:40 GMT\nx-ms-meta-m1:v1\n\
+             x-ms-meta-m2:v2\n/testaccount1/mycontainer/hello.txt"
+        );
+    }
+
+    #[tokio::test]
+    async fn string_to_sign_with_x_ms_date_and_with_date() {
+        let date = "Tue, 14 Nov 2023 22:13:20 GMT";
+        let resource = "/devstoreaccount1/devstoreaccount1/dates/messages";
+        for date_header in [false, true] {
+            let mut client =
+                QueueClientBuilder::new().emulator().queue_name("dates").date_header(date_header).build().unwrap();
+            client.now = stopped_clock;
+            let details = client.dry_run(reqwest::Method::GET, "/messages", &[], None).await.unwrap();
+            let headers = details.request.headers();
+            let expected = match date_header {
+                // the date in the canonicalized headers, the Date line empty
+                false => {
+                    assert_eq!(headers["x-ms-date"], date);
+                    assert!(headers.get("date").is_none());
+                    format!("GET\n\n\n\n\n\n\n\n\n\n\n\nx-ms-date:{}\nx-ms-version:2021-12-02\n{}", date, resource)
+                }
+                // the Date line filled, no x-ms-date anywhere
+                true => {
+                    assert_eq!(headers["date"], date);
+                    assert!(headers.get("x-ms-date").is_none());
+                    format!("GET\n\n\n\n\n\n{}\n\n\n\n\n\nx-ms-version:2021-12-02\n{}", date, resource)
+                }
+            };
+            assert_eq!(details.string_to_sign.unwrap(), expected);
+        }
+    }
+
+    fn header(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn ms_headers_are_normalised_sorted_and_joined() {
+        let headers = [
+            header("x-ms-version", "2021-12-02"),
+            header("Content-Type", "application/xml"),
+            header("X-MS-Date", "  Sat, 21 Feb 2015   00:48:38 GMT "),
+            header("x-ms-meta-tags", "a"),
+            header("x-ms-meta-Owner", "billing\r\n team"),
+            header("x-ms-meta-tags", "b"),
+            header("x-ms-date-foo", "bar"),
+            header("Authorization", "SharedKey a:b"),
+        ];
+        let map: Vec<(String, String)> = ms_header_map(&headers).into_iter().collect();
+        assert_eq!(
+            map,
+            vec![
+                header("x-ms-date", "Sat, 21 Feb 2015 00:48:38 GMT"),
+                header("x-ms-date-foo", "bar"),
+                header("x-ms-meta-owner", "billing team"),
+                header("x-ms-meta-tags", "a,b"),
+                header("x-ms-version", "2021-12-02"),
+            ]
+        );
+        assert_eq!(
+            canonical_headers(&headers),
+            "x-ms-date:Sat, 21 Feb 2015 00:48:38 GMT\nx-ms-date-foo:bar\nx-ms-meta-owner:billing team\n\
+             x-ms-meta-tags:a,b\nx-ms-version:2021-12-02"
+        );
+    }
+
+    #[tokio::test]
+    async fn content_md5_matches_known_vectors_and_is_signed() {
+        // rfc 1321's d41d8cd98f00b204e9800998ecf8427e and the fox's 9e107d9d372bb6826bd81d3542a419d6, base64'd
+        assert_eq!(content_md5(b""), "1B2M2Y8AsgTpgAmY7PhCfg==");
+        assert_eq!(content_md5(b"The quick brown fox jumps over the lazy dog"), "nhB9nTcrtoJr2B01QqQZ1g==");
+
+        let client = QueueClientBuilder::new().emulator().queue_name("md5").verify_content_md5(true).build().unwrap();
+        let body = "The quick brown fox jumps over the lazy dog".to_string();
+        let details = client.dry_run(reqwest::Method::POST, "/messages", &[], Some(body)).await.unwrap();
+        assert_eq!(details.request.headers()["content-md5"], "nhB9nTcrtoJr2B01QqQZ1g==");
+        // verb, content encoding, content language, content length, then content md5
+        assert_eq!(details.string_to_sign.unwrap().split('\n').nth(4), Some("nhB9nTcrtoJr2B01QqQZ1g=="));
+    }
+
+    #[tokio::test]
+    async fn rotated_key_signs_the_next_request_on_the_same_http_client() {
+        let key_a = connection_string::EMULATOR_ACCOUNT_KEY;
+        let key_b = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8gISIjJCUmJygpKissLS4vMDEyMzQ1Njc4OTo7PD0+Pw==";
+        let ok = || ("200 OK", String::new(), empty_list());
+        let (endpoint, requests) = serve(vec![ok(), ok(), ok()]).await;
+        // our own http client, so its user agent shows it's the one every request goes out on
+        let http = reqwest::Client::builder().user_agent("rotation-test").build().unwrap();
+        let client = QueueClientBuilder::new()
+            .emulator()
+            .endpoint(&endpoint)
+            .queue_name("rotating")
+            .http_client(http)
+            .build()
+            .unwrap();
+        let clone = client.clone();
+
+        client.get_messages(1, None).await.unwrap();
+        assert!(client.set_account_key("not a key").is_err());
+        client.set_account_key(key_b).unwrap();
+        client.get_messages(1, None).await.unwrap();
+        // clones share the credential, so they've switched too
+        clone.get_messages(1, None).await.unwrap();
+
+        let seen = all_requests(requests).await;
+        let resource = "/devstoreaccount1/devstoreaccount1/rotating/messages\nnumofmessages:1";
+        let signed_with = |request: &String, key: &str| {
+            let authorization = request_header(request, "authorization").unwrap();
+            authorization == format!("SharedKey devstoreaccount1:{}", server_signature(request, key, resource))
+        };
+        assert!(signed_with(&seen[0], key_a));
+        assert!(signed_with(&seen[1], key_b));
+        assert!(signed_with(&seen[2], key_b));
+        for request in &seen {
+            assert_eq!(request_header(request, "user-agent").as_deref(), Some("rotation-test"));
+        }
+    }
+
+    #[tokio::test]
+    async fn sas_token_can_be_swapped_too() {
+        let (endpoint, requests) = serve(vec![("200 OK", String::new(), empty_list()); 2]).await;
+        let client = QueueClientBuilder::new()
+            .account_name("devstoreaccount1")
+            .endpoint(&endpoint)
+            .queue_name("rotating")
+            .sas_token("sv=2021-12-02&sp=p&sig=first")
+            .build()
+            .unwrap();
+
+        client.get_messages(1, None).await.unwrap();
+        client.set_sas_token("?sv=2021-12-02&sp=p&sig=second").unwrap();
+        assert!(client.set_account_key(connection_string::EMULATOR_ACCOUNT_KEY).is_err());
+        client.get_messages(1, None).await.unwrap();
+
+        let seen = all_requests(requests).await;
+        assert!(seen[0].lines().next().unwrap().contains("sig=first"));
+        assert!(seen[1].lines().next().unwrap().contains("sig=second"));
     }
 }
@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+
+use crate::token::{AccessToken, TokenCache, TokenCredential, STORAGE_RESOURCE};
+use crate::{BuildError, QueueError};
+
+/// the instance metadata service, only reachable from inside an azure vm / aks node.
+const IMDS_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+const IMDS_API_VERSION: &str = "2018-02-01";
+/// IMDS throttles and occasionally 5xxs while it's busy, they say to retry with backoff.
+/// https://learn.microsoft.com/en-us/entra/identity/managed-identities-azure-resources/how-to-use-vm-token#error-handling
+const IMDS_MAX_ATTEMPTS: u32 = 5;
+const IMDS_BASE_DELAY_MS: u64 = 500;
+/// off azure 169.254.169.254 just doesn't answer, so don't wait long to find that out. Matters when this is
+/// one link in a `ChainedCredential`.
+const IMDS_CONNECT_TIMEOUT_SECS: u64 = 2;
+
+/// gets tokens for the vm / aks node's managed identity from IMDS, so no storage keys need to be shipped.
+/// leave `client_id` as `None` for the system assigned identity, or give the client id of a user assigned one.
+/// tokens are cached until a few minutes before they expire, so this is cheap to call per request.
+#[derive(Debug)]
+pub struct ManagedIdentityCredential {
+    client_id: Option<String>,
+    http: reqwest::Client,
+    cache: TokenCache,
+}
+
+/// IMDS sends the numbers back as strings, e.g. `"expires_on": "1586984735"`
+#[derive(Deserialize)]
+struct ImdsToken {
+    access_token: String,
+    expires_on: String,
+}
+
+impl ManagedIdentityCredential {
+    /// the system assigned identity. It has its own http client for IMDS, which is a
+    /// `BuildError::HttpClient` if reqwest can't make one (usually the tls backend).
+    pub fn new() -> Result<ManagedIdentityCredential, BuildError> {
+        ManagedIdentityCredential::from_client_id(None)
+    }
+
+    /// a user assigned identity, by its client id.
+    pub fn with_client_id(client_id: &str) -> Result<ManagedIdentityCredential, BuildError> {
+        ManagedIdentityCredential::from_client_id(Some(client_id.to_string()))
+    }
+
+    fn from_client_id(client_id: Option<String>) -> Result<ManagedIdentityCredential, BuildError> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(IMDS_CONNECT_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| BuildError::HttpClient(e.to_string()))?;
+        Ok(ManagedIdentityCredential {
+            client_id,
+            http,
+            cache: TokenCache::default(),
+        })
+    }
+
+    async fn request_token(&self) -> Result<AccessToken, QueueError> {
+        let mut query = vec![("api-version", IMDS_API_VERSION), ("resource", STORAGE_RESOURCE)];
+        if let Some(client_id) = &self.client_id {
+            query.push(("client_id", client_id));
+        }
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let response = self
+                .http
+                .get(IMDS_TOKEN_URL)
+                .query(&query)
+                .header("Metadata", "true")
+                .send()
+                .await
+                .map_err(|e| QueueError::Credential(format!("managed identity endpoint unreachable: {}", e)))?;
+
+            let status = response.status();
+            if status.is_success() {
+                let token: ImdsToken = response
+                    .json()
+                    .await
+                    .map_err(|e| QueueError::Credential(format!("unexpected managed identity response: {}", e)))?;
+                return to_access_token(token);
+            }
+
+            let retryable = status.as_u16() == 429 || status.is_server_error();
+            let body = response.text().await.unwrap_or_default();
+            if !retryable || attempt >= IMDS_MAX_ATTEMPTS {
+                return Err(QueueError::Credential(format!(
+                    "managed identity token request failed with {} after {} attempt(s): {}",
+                    status, attempt, body
+                )));
+            }
+            tokio::time::sleep(Duration::from_millis(IMDS_BASE_DELAY_MS * 2u64.pow(attempt - 1))).await;
+        }
+    }
+}
+
+fn to_access_token(token: ImdsToken) -> Result<AccessToken, QueueError> {
+    let expires_on = token
+        .expires_on
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+        .ok_or_else(|| {
+            QueueError::Credential(format!("managed identity expires_on '{}' is not a timestamp", token.expires_on))
+        })?;
+    Ok(AccessToken {
+        token: token.access_token,
+        expires_on,
+    })
+}
+
+impl TokenCredential for ManagedIdentityCredential {
+    fn get_token(&self) -> BoxFuture<'_, Result<AccessToken, QueueError>> {
+        Box::pin(self.cache.get_or_refresh(|| self.request_token()))
+    }
+}
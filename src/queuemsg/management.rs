@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::{QueueClient, QueueError};
+
+/// how often `wait_for_deletion` checks whether the queue is gone yet
+pub const DELETION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// what `get_properties` found out about the queue.
+#[derive(Debug, Clone)]
+pub struct QueueProperties {
+    /// roughly how many messages are in the queue. Azure says it's not lower than the real number, but it can be
+    /// higher, so it's good for dashboards and autoscaling rather than exact counting.
+    pub approximate_message_count: u64,
+    /// user metadata with the `x-ms-meta-` taken off the names. Azure hands names back lowercased.
+    pub metadata: HashMap<String, String>,
+}
+
+/// what `get_metadata` found out, with the names the Get Queue Metadata operation uses: the count is
+/// `x-ms-approximate-messages-count` as an integer, a header that isn't one is a `QueueError::InvalidResponse`.
+#[derive(Debug, Clone)]
+pub struct QueueMetadata {
+    /// the same rough count as `QueueProperties::approximate_message_count`, never lower than the real number
+    pub approximate_messages_count: u64,
+    /// user metadata with the `x-ms-meta-` taken off the names, lowercased by azure.
+    pub metadata: HashMap<String, String>,
+}
+
+/// what `create_queue` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateQueueOutcome {
+    /// it wasn't there and now it is, a 201
+    Created,
+    /// it was already there with the same metadata, a 204. Nothing changed.
+    AlreadyExists,
+}
+
+/// queue lifecycle: everything here is on the queue itself, `/{queue}` rather than `/{queue}/messages`.
+impl QueueClient {
+    /// create the queue, or find it's already there (azure says 204 rather than 201 for that).
+    /// a queue that's already there with different metadata is a `QueueError::Service` 409 QueueAlreadyExists,
+    /// and one that's still being deleted is a 409 QueueBeingDeleted.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/create-queue4
+    pub async fn create_queue(&self) -> Result<CreateQueueOutcome, QueueError> {
+        self.create_queue_with_metadata(HashMap::new()).await
+    }
+
+    /// `create_queue` with user metadata set on the new queue, the same rules as `set_metadata`. If the queue is
+    /// already there its metadata has to be exactly this for `AlreadyExists`, anything else is the 409.
+    pub async fn create_queue_with_metadata(
+        &self,
+        meta: HashMap<String, String>,
+    ) -> Result<CreateQueueOutcome, QueueError> {
+        let headers = metadata_headers(meta)?;
+        let response = self
+            .execute_with_headers(reqwest::Method::PUT, "", &[], &headers, None)
+            .await
+            .map_err(|e| self.queue_state_error(e))?;
+        match response.status {
+            201 => Ok(CreateQueueOutcome::Created),
+            204 => Ok(CreateQueueOutcome::AlreadyExists),
+            // a 200 or 202 doesn't say which it was, and guessing wrong could skip setting up a new queue
+            status => Err(QueueError::InvalidResponse(format!(
+                "create queue answered {}, expected 201 (created) or 204 (already exists)",
+                status
+            ))),
+        }
+    }
+
+    /// delete the queue and every message in it. Azure takes a while to actually get rid of it, creating one
+    /// with the same name straight afterwards gets a 409 QueueBeingDeleted for up to 30 seconds or so.
+    /// a queue that isn't there is `QueueError::QueueNotFound`, and one already being deleted
+    /// `QueueError::QueueBeingDeleted`, so offboarding code can treat both as done if it likes.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/delete-queue3
+    pub async fn delete_queue(&self) -> Result<(), QueueError> {
+        self.execute(reqwest::Method::DELETE, "", &[], None)
+            .await
+            .map_err(|e| self.queue_state_error(e))?;
+        Ok(())
+    }
+
+    /// after `delete_queue`, wait until azure says the queue isn't there any more, checking every
+    /// `DELETION_POLL_INTERVAL`. `true` once it's gone, `false` if it was still there when `timeout` ran out.
+    /// the name can still be refused with QueueBeingDeleted for a moment after it stops showing up, so code that
+    /// creates it again straight after should still be ready to retry that.
+    pub async fn wait_for_deletion(&self, timeout: Duration) -> Result<bool, QueueError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.get_properties().await {
+                Err(QueueError::QueueNotFound { .. }) => return Ok(true),
+                Err(e) => return Err(e),
+                Ok(_) if Instant::now() >= deadline => return Ok(false),
+                Ok(_) => tokio::time::sleep(DELETION_POLL_INTERVAL.min(deadline - Instant::now())).await,
+            }
+        }
+    }
+
+    /// the approximate message count and user metadata for the queue.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/get-queue-metadata
+    pub async fn get_properties(&self) -> Result<QueueProperties, QueueError> {
+        let query = [("comp", "metadata".to_string())];
+        let response = self
+            .execute(reqwest::Method::GET, "", &query, None)
+            .await
+            .map_err(|e| self.queue_state_error(e))?;
+        let count = response
+            .headers
+            .get("x-ms-approximate-messages-count")
+            .ok_or_else(|| {
+                QueueError::InvalidResponse("response is missing the x-ms-approximate-messages-count header".to_string())
+            })?;
+        let approximate_message_count = count.parse().map_err(|_| {
+            QueueError::InvalidResponse(format!("x-ms-approximate-messages-count '{}' is not a number", count))
+        })?;
+        let metadata = response
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.strip_prefix("x-ms-meta-")?.to_string(), value.clone())))
+            .collect();
+        Ok(QueueProperties {
+            approximate_message_count,
+            metadata,
+        })
+    }
+
+    /// replace the queue's user metadata with `meta`, e.g. `owner` or `environment` tags. Whatever was there
+    /// before goes, so an empty map clears it (`merge_metadata` keeps it). Each pair is sent (and signed) as an `x-ms-meta-{name}` header.
+    /// names have to be valid C# identifiers (letters, digits and `_`, not starting with a digit, anything else
+    /// is a `QueueError::InvalidMetadataName`) and come back lowercased from `get_properties`. Values have to fit
+    /// in a header, so visible ascii and spaces only.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/set-queue-metadata
+    pub async fn set_metadata(&self, meta: HashMap<String, String>) -> Result<(), QueueError> {
+        let headers = metadata_headers(meta)?;
+        let query = [("comp", "metadata".to_string())];
+        self.execute_with_headers(reqwest::Method::PUT, "", &query, &headers, None).await?;
+        Ok(())
+    }
+
+    /// the queue depth and user metadata as a `QueueMetadata`, for code written against the REST operation's
+    /// names. It's the same single `GET {queue}?comp=metadata` as `get_properties`.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/get-queue-metadata
+    pub async fn get_metadata(&self) -> Result<QueueMetadata, QueueError> {
+        let properties = self.get_properties().await?;
+        Ok(QueueMetadata {
+            approximate_messages_count: properties.approximate_message_count,
+            metadata: properties.metadata,
+        })
+    }
+
+    /// add `meta` to the queue's metadata rather than replacing it: `get_properties`, then `set_metadata` with the
+    /// two put together, `meta` winning where a name is in both. Names are compared ignoring case, like azure
+    /// does. It's two calls, not one, so something else setting metadata in between can have its change lost.
+    pub async fn merge_metadata(&self, meta: HashMap<String, String>) -> Result<(), QueueError> {
+        for (name, value) in &meta {
+            validate_metadata(name, value)?;
+        }
+        let mut merged = self.get_properties().await?.metadata;
+        for (name, value) in meta {
+            merged.retain(|existing, _| !existing.eq_ignore_ascii_case(&name));
+            merged.insert(name, value);
+        }
+        self.set_metadata(merged).await
+    }
+
+    /// just the approximate message count from `get_properties`, for autoscaling triggers and the like.
+    pub async fn approximate_message_count(&self) -> Result<u64, QueueError> {
+        Ok(self.get_properties().await?.approximate_message_count)
+    }
+
+    /// QueueNotFound and QueueBeingDeleted as their own variants, anything else as it was
+    fn queue_state_error(&self, error: QueueError) -> QueueError {
+        let queue_name = self.queue_name().to_string();
+        match error.error_code() {
+            Some("QueueNotFound") => QueueError::QueueNotFound {
+                queue_name,
+                error: Box::new(error),
+            },
+            Some("QueueBeingDeleted") => QueueError::QueueBeingDeleted {
+                queue_name,
+                error: Box::new(error),
+            },
+            _ => error,
+        }
+    }
+}
+
+/// each pair as an `x-ms-meta-{name}` header, checked
+fn metadata_headers(meta: HashMap<String, String>) -> Result<Vec<(String, String)>, QueueError> {
+    let mut headers = Vec::<(String, String)>::new();
+    for (name, value) in meta {
+        validate_metadata(&name, &value)?;
+        headers.push((format!("x-ms-meta-{}", name), value));
+    }
+    Ok(headers)
+}
+
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/naming-queues-and-metadata#metadata-names
+fn validate_metadata(name: &str, value: &str) -> Result<(), QueueError> {
+    let identifier = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !identifier {
+        return Err(QueueError::InvalidMetadataName(name.to_string()));
+    }
+    if !value.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+        return Err(QueueError::InvalidConfig(format!(
+            "metadata value for '{}' must be visible ascii, encode anything else (base64, percent encoding...)",
+            name
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection_string::EMULATOR_ACCOUNT_KEY;
+    use crate::tests::{all_requests, request_header, serve, server_signature};
+    use crate::{canonical_headers, QueueClientBuilder};
+
+    #[tokio::test]
+    async fn metadata_headers_are_signed() {
+        let (endpoint, requests) = serve(vec![("204 No Content", String::new(), String::new())]).await;
+        let client = QueueClientBuilder::new().emulator().endpoint(&endpoint).queue_name("tagged").build().unwrap();
+        let meta = HashMap::from([
+            ("owner".to_string(), "billing".to_string()),
+            ("Environment".to_string(), "prod  eu".to_string()),
+            ("cost_centre".to_string(), "42".to_string()),
+        ]);
+
+        client.set_metadata(meta).await.unwrap();
+        let request = all_requests(requests).await.remove(0);
+        let sent: Vec<(String, String)> = request
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+            .collect();
+        // lowercased, sorted and in among the client's own x-ms- headers
+        let block = canonical_headers(&sent);
+        let names: Vec<&str> = block.lines().map(|line| line.split(':').next().unwrap()).collect();
+        assert_eq!(
+            names,
+            vec!["x-ms-date", "x-ms-meta-cost_centre", "x-ms-meta-environment", "x-ms-meta-owner", "x-ms-version"]
+        );
+        assert!(block.contains("\nx-ms-meta-environment:prod eu\n"));
+
+        // and the signature is over exactly what was sent
+        let resource = "/devstoreaccount1/devstoreaccount1/tagged\ncomp:metadata";
+        let signature = server_signature(&request, EMULATOR_ACCOUNT_KEY, resource);
+        let authorization = request_header(&request, "authorization").unwrap();
+        assert_eq!(authorization, format!("SharedKey devstoreaccount1:{}", signature));
+    }
+}
@@ -0,0 +1,912 @@
+use std::fmt;
+use std::future::Future;
+
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+
+use crate::builder::{validate_message_ttl, validate_put_visibility_timeout, MAX_SECONDS};
+use crate::{is_transient, retry_after, xml, QueueClient, QueueError, QueueResponse, SignedRequest};
+
+/// the most messages azure will hand out in one get.
+const MAX_MESSAGES_PER_GET: u32 = 32;
+
+/// the biggest message text azure will store, in bytes, after any base64 encoding. So 48 KB of your own text
+/// when it's base64'd.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/put-message#request-body
+const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// before this x-ms-version it was 8 KB.
+const LARGE_MESSAGE_API_VERSION: &str = "2011-08-18";
+const OLD_MAX_MESSAGE_BYTES: usize = 8 * 1024;
+
+/// how message text is put into the `<MessageText>` element.
+/// the official azure sdks base64 the text by default so anything survives the trip, and so do we. `None`
+/// sends the (xml escaped) text as is, which is what you want if something else reads the queue expecting that.
+/// whatever you pick, every producer and consumer of a queue has to agree - a base64 consumer reading a raw
+/// message will either fail to decode it or, worse, decode it into garbage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MessageEncoding {
+    None,
+    #[default]
+    Base64,
+}
+
+impl MessageEncoding {
+    fn encode(&self, text: &str) -> String {
+        match self {
+            MessageEncoding::None => text.to_string(),
+            MessageEncoding::Base64 => general_purpose::STANDARD.encode(text),
+        }
+    }
+
+    /// the error is just why, the caller knows which message it was
+    fn decode(&self, text: &str) -> Result<String, String> {
+        match self {
+            MessageEncoding::None => Ok(text.to_string()),
+            MessageEncoding::Base64 => {
+                let bytes = general_purpose::STANDARD.decode(text).map_err(|e| {
+                    format!("message text is not base64 ({}), is the producer using the same encoding?", e)
+                })?;
+                String::from_utf8(bytes).map_err(|_| "base64 decoded message text is not utf-8".to_string())
+            }
+        }
+    }
+}
+
+/// a message read off the queue by `get_messages`.
+/// hang on to `message_id` and `pop_receipt` - you need both to delete the message once you're done with it.
+#[derive(Debug, Clone)]
+pub struct QueueMessage {
+    pub message_id: String,
+    pub pop_receipt: String,
+    pub message_text: String,
+    pub dequeue_count: u32,
+    pub insertion_time: DateTime<Utc>,
+    pub expiration_time: DateTime<Utc>,
+    pub time_next_visible: DateTime<Utc>,
+}
+
+impl QueueMessage {
+    /// `client.delete_message` with this message's id and pop receipt. `client` has to be for the queue the
+    /// message came from.
+    pub async fn delete(&self, client: &QueueClient) -> Result<(), QueueError> {
+        client.delete_message(&self.message_id, &self.pop_receipt).await
+    }
+
+    /// `client.update_message` for this message, keeping it up to date with the new pop receipt, next visible
+    /// time and (if given) text, so it can be updated again or deleted straight after. Nothing changes here if
+    /// the update fails.
+    pub async fn update(
+        &mut self,
+        client: &QueueClient,
+        visibility_timeout: u32,
+        new_text: Option<String>,
+    ) -> Result<(), QueueError> {
+        let result = client
+            .update_message(&self.message_id, &self.pop_receipt, visibility_timeout, new_text.clone())
+            .await?;
+        self.pop_receipt = result.pop_receipt;
+        self.time_next_visible = result.time_next_visible;
+        if let Some(text) = new_text {
+            self.message_text = text;
+        }
+        Ok(())
+    }
+}
+
+/// a message looked at with `peek_messages`. It's still on the queue and still visible to everyone, so there's
+/// no pop receipt (you can't delete or update it from a peek) and no next visible time.
+#[derive(Debug, Clone)]
+pub struct PeekedMessage {
+    pub message_id: String,
+    pub message_text: String,
+    pub dequeue_count: u32,
+    pub insertion_time: DateTime<Utc>,
+    pub expiration_time: DateTime<Utc>,
+}
+
+/// what azure says about a message `send_message` just put on the queue. `message_id` and `pop_receipt` are
+/// enough to delete or update it before anyone has read it (cancelling a scheduled job, say).
+#[derive(Debug, Clone)]
+pub struct SentMessage {
+    pub message_id: String,
+    pub pop_receipt: String,
+    pub insertion_time: DateTime<Utc>,
+    pub expiration_time: DateTime<Utc>,
+    pub time_next_visible: DateTime<Utc>,
+}
+
+/// per message settings for `send_message_with_options`. Anything left as `None` falls back to the client's
+/// default (see `QueueClientBuilder`), and then to azure's.
+/// `let mut options = SendOptions::default(); options.message_ttl = Some(60);`
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct SendOptions {
+    /// how many seconds the message lives on the queue before azure throws it away, azure's default is 7 days.
+    /// -1 means never expire, which needs x-ms-version 2017-07-29 or later (as does anything over 7 days).
+    pub message_ttl: Option<i64>,
+    /// how many seconds before the message shows up for `get_messages`, for deferring work until later.
+    /// 0 to 7 days, and it has to be less than the ttl.
+    pub initial_visibility: Option<u32>,
+}
+
+/// settings for `receive_messages`. `None` leaves the parameter off and azure decides: one message, hidden for
+/// 30 seconds.
+/// `let mut options = ReceiveOptions::default(); options.num_messages = Some(32);`
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct ReceiveOptions {
+    /// how many messages to take, 1-32
+    pub num_messages: Option<u32>,
+    /// seconds the messages are hidden from everyone else for, 1 second to 7 days. They come back after that
+    /// unless they've been deleted.
+    pub visibility_timeout: Option<u32>,
+}
+
+/// what `update_message` gives back. The old pop receipt is dead after an update, use this one for the next
+/// update or the delete.
+#[derive(Debug, Clone)]
+pub struct UpdateResult {
+    pub pop_receipt: String,
+    pub time_next_visible: DateTime<Utc>,
+}
+
+/// the queue message is actually XML (no, I don't know why when every other azure service consumes JSON)
+/// The XML format is simple and static so we construct it manually rather than using `serde_xml_rs` or another
+/// sane XML parsing crate. The text does need escaping though, otherwise a message with `&` or `<` in it
+/// is malformed and azure either rejects it or stores something that isn't what you sent.
+/// base64 output never needs escaping, but it doesn't hurt.
+/// `encoded_text` has already been through the client's `MessageEncoding`.
+fn create_content_string(encoded_text: &str) -> String {
+    let mut content_string = Vec::<String>::new();
+    content_string.push("<QueueMessage>\n".to_string());
+    content_string.push(format!("<MessageText>{}</MessageText>\n", xml::escape(encoded_text)));
+    content_string.push("</QueueMessage>".to_string());
+    content_string.join("")
+}
+
+/// get messages comes back as:
+/// ```text
+/// <QueueMessagesList>
+///   <QueueMessage>
+///     <MessageId>string-message-id</MessageId>
+///     <InsertionTime>insertion-time</InsertionTime>
+///     <ExpirationTime>expiration-time</ExpirationTime>
+///     <PopReceipt>opaque-string-receipt-data</PopReceipt>
+///     <TimeNextVisible>time-next-visible</TimeNextVisible>
+///     <DequeueCount>integer</DequeueCount>
+///     <MessageText>message-body</MessageText>
+///   </QueueMessage>
+/// </QueueMessagesList>
+/// ```
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/get-messages#response-body
+/// public so you can parse a body you got some other way, e.g. from a recorded response. `encoding` is how
+/// the message text was put on the queue. An empty list is an empty `Vec`, and a missing `DequeueCount` or
+/// `MessageText` (which put message leaves out) comes back as 0 and "".
+/// the first message that doesn't decode is a `QueueError::Decode` with that message in it, like `get_typed`.
+pub fn parse_messages_list(body: &str, encoding: MessageEncoding) -> Result<Vec<QueueMessage>, QueueError> {
+    parse_each_message(body, encoding)?.into_iter().collect()
+}
+
+/// `parse_messages_list` one message at a time: the outer error is a body we can't read at all, the inner
+/// ones messages that didn't decode, so one bad message doesn't take the rest of the batch with it.
+fn parse_each_message(
+    body: &str,
+    encoding: MessageEncoding,
+) -> Result<Vec<Result<QueueMessage, QueueError>>, QueueError> {
+    let mut messages = Vec::<Result<QueueMessage, QueueError>>::new();
+    for message in xml::elements(body, "QueueMessage") {
+        let peeked = parse_raw_message(message)?;
+        let mut received = QueueMessage {
+            message_id: peeked.message_id,
+            pop_receipt: required(message, "PopReceipt")?,
+            message_text: peeked.message_text,
+            dequeue_count: peeked.dequeue_count,
+            insertion_time: peeked.insertion_time,
+            expiration_time: peeked.expiration_time,
+            time_next_visible: required_time(message, "TimeNextVisible")?,
+        };
+        messages.push(match encoding.decode(&received.message_text) {
+            Ok(text) => {
+                received.message_text = text;
+                Ok(received)
+            }
+            Err(error) => Err(QueueError::Decode {
+                message: Box::new(received),
+                error,
+            }),
+        });
+    }
+    Ok(messages)
+}
+
+/// put message answers with one message in the same `QueueMessagesList` as get, just without the text and
+/// dequeue count:
+/// ```text
+/// <QueueMessagesList>
+///   <QueueMessage>
+///     <MessageId>string-message-id</MessageId>
+///     <InsertionTime>insertion-time</InsertionTime>
+///     <ExpirationTime>expiration-time</ExpirationTime>
+///     <PopReceipt>opaque-string-receipt-data</PopReceipt>
+///     <TimeNextVisible>time-next-visible</TimeNextVisible>
+///   </QueueMessage>
+/// </QueueMessagesList>
+/// ```
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/put-message#response-body
+/// public for reading the response to a request from `build_put_message_request` that you sent yourself.
+pub fn parse_sent_message(body: &str) -> Result<SentMessage, QueueError> {
+    let message = xml::elements(body, "QueueMessage")
+        .into_iter()
+        .next()
+        .ok_or_else(|| QueueError::InvalidResponse("put message response has no QueueMessage in it".to_string()))?;
+    Ok(SentMessage {
+        message_id: required(message, "MessageId")?,
+        pop_receipt: required(message, "PopReceipt")?,
+        insertion_time: required_time(message, "InsertionTime")?,
+        expiration_time: required_time(message, "ExpirationTime")?,
+        time_next_visible: required_time(message, "TimeNextVisible")?,
+    })
+}
+
+/// peek comes back as the same `QueueMessagesList` as get, minus `PopReceipt` and `TimeNextVisible`
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/peek-messages#response-body
+fn parse_peeked_messages_list(body: &str, encoding: MessageEncoding) -> Result<Vec<PeekedMessage>, QueueError> {
+    let mut messages = Vec::<PeekedMessage>::new();
+    for message in xml::elements(body, "QueueMessage") {
+        let mut peeked = parse_raw_message(message)?;
+        // there's no pop receipt to delete it with, so just say which one it was
+        peeked.message_text = encoding.decode(&peeked.message_text).map_err(|error| {
+            QueueError::InvalidResponse(format!("couldn't decode message {}: {}", peeked.message_id, error))
+        })?;
+        messages.push(peeked);
+    }
+    Ok(messages)
+}
+
+/// the part of one `<QueueMessage>` that get and peek have in common, everything but the pop receipt and next
+/// visible time. The text is unescaped but not decoded yet, so `&lt;` in a message sent without base64 is `<`
+/// again and a base64 message is still base64.
+fn parse_raw_message(message: &str) -> Result<PeekedMessage, QueueError> {
+    Ok(PeekedMessage {
+        message_id: required(message, "MessageId")?,
+        message_text: xml::element(message, "MessageText").map(xml::unescape).unwrap_or_default(),
+        dequeue_count: optional_count(message)?,
+        insertion_time: required_time(message, "InsertionTime")?,
+        expiration_time: required_time(message, "ExpirationTime")?,
+    })
+}
+
+fn optional_count(message: &str) -> Result<u32, QueueError> {
+    match xml::element(message, "DequeueCount") {
+        Some(dequeue_count) => dequeue_count
+            .trim()
+            .parse()
+            .map_err(|_| QueueError::InvalidResponse(format!("DequeueCount '{}' is not a number", dequeue_count))),
+        None => Ok(0),
+    }
+}
+
+fn required(message: &str, tag: &str) -> Result<String, QueueError> {
+    xml::element(message, tag)
+        .map(xml::unescape)
+        .ok_or_else(|| QueueError::InvalidResponse(format!("QueueMessage is missing {}", tag)))
+}
+
+/// timestamps come back in the same RFC1123-ish format we send in x-ms-date, e.g. `Fri, 09 Oct 2009 21:04:30 GMT`
+fn required_time(message: &str, tag: &str) -> Result<DateTime<Utc>, QueueError> {
+    parse_time(tag, &required(message, tag)?)
+}
+
+fn parse_time(name: &str, value: &str) -> Result<DateTime<Utc>, QueueError> {
+    DateTime::parse_from_rfc2822(value)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|_| QueueError::InvalidResponse(format!("{} '{}' is not a valid date", name, value)))
+}
+
+fn required_header<'a>(response: &'a QueueResponse, name: &str) -> Result<&'a str, QueueError> {
+    response
+        .headers
+        .get(name)
+        .map(String::as_str)
+        .ok_or_else(|| QueueError::InvalidResponse(format!("response is missing the {} header", name)))
+}
+
+/// azure measures the text as it's stored, so after base64 but before xml escaping (escapes are undone on
+/// its end). Too big and it's a 400 with nothing useful in it, so we'd rather say so before sending.
+fn validate_message_size(encoded_text: &str, encoding: MessageEncoding, api_version: &str) -> Result<(), QueueError> {
+    // the versions are dates so plain string comparison orders them
+    let limit = match api_version >= LARGE_MESSAGE_API_VERSION {
+        true => MAX_MESSAGE_BYTES,
+        false => OLD_MAX_MESSAGE_BYTES,
+    };
+    if encoded_text.len() <= limit {
+        return Ok(());
+    }
+    Err(QueueError::InvalidConfig(match encoding {
+        MessageEncoding::Base64 => format!(
+            "message is {} bytes once base64 encoded, azure takes at most {} ({} before encoding)",
+            encoded_text.len(),
+            limit,
+            limit / 4 * 3
+        ),
+        MessageEncoding::None => {
+            format!("message is {} bytes, azure takes at most {}", encoded_text.len(), limit)
+        }
+    }))
+}
+
+/// a 400 PopReceiptMismatch as `QueueError::PopReceiptMismatch`, anything else as it was
+fn pop_receipt_mismatch(message_id: &str, error: QueueError) -> QueueError {
+    match error.error_code() == Some("PopReceiptMismatch") {
+        true => QueueError::PopReceiptMismatch {
+            message_id: message_id.to_string(),
+            error: Box::new(error),
+        },
+        false => error,
+    }
+}
+
+/// how many times `clear_messages` calls clear messages on a queue that keeps timing out before giving up.
+/// each call gets 30 seconds of deleting, so this is a very big queue or one that's being filled as fast.
+/// ordinary retries of a busy or unreachable service count towards it too.
+pub const MAX_CLEAR_CALLS: u32 = 100;
+
+fn validate_num_messages(num: u32) -> Result<(), QueueError> {
+    if num == 0 || num > MAX_MESSAGES_PER_GET {
+        return Err(QueueError::InvalidConfig(format!(
+            "number of messages must be between 1 and {}, got {}",
+            MAX_MESSAGES_PER_GET, num
+        )));
+    }
+    Ok(())
+}
+
+impl QueueClient {
+    /// the put/update body for `text`, encoded the way this client encodes and checked for size.
+    fn message_body(&self, text: &str) -> Result<String, QueueError> {
+        let encoded_text = self.message_encoding.encode(text);
+        validate_message_size(&encoded_text, self.message_encoding, &self.api_version)?;
+        Ok(create_content_string(&encoded_text))
+    }
+
+    /// put a message on the queue, and get back the id and pop receipt azure gave it.
+    /// anything other than a 2xx from azure comes back as `QueueError::Service` with whatever the body said,
+    /// which is normally an xml blob with an error code in it.
+    /// the client's default ttl and visibility timeout go on the query string if they're set.
+    pub async fn send_message(&self, message_text: String) -> Result<SentMessage, QueueError> {
+        self.send_message_with_options(message_text, &SendOptions::default()).await
+    }
+
+    /// `send_message` for each of `messages`, up to `max_concurrency` at a time (at least 1). The queue service
+    /// has no batch put, so this is just that many separate requests in flight: each one is signed, retried and
+    /// billed on its own, and any of them can fail without the others noticing.
+    /// the results line up with `messages`, the nth result is the nth message. The order they land on the queue
+    /// doesn't, it's whatever order the puts finish in, so don't use this for anything that has to be read back
+    /// in order.
+    pub async fn put_batch(
+        &self,
+        messages: Vec<String>,
+        max_concurrency: usize,
+    ) -> Vec<Result<SentMessage, QueueError>> {
+        let mut results: Vec<(usize, Result<SentMessage, QueueError>)> =
+            stream::iter(messages.into_iter().enumerate())
+                .map(|(index, message_text)| async move { (index, self.send_message(message_text).await) })
+                .buffer_unordered(max_concurrency.max(1))
+                .collect()
+                .await;
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// `send_message` with settings for just this message, e.g. a shorter ttl for something that's useless
+    /// after a minute, or an initial visibility to run a job later without a scheduler. They win over the
+    /// client's defaults.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/put-message
+    pub async fn send_message_with_options(
+        &self,
+        message_text: String,
+        options: &SendOptions,
+    ) -> Result<SentMessage, QueueError> {
+        let body_content = self.message_body(&message_text)?;
+        let query = self.put_message_query(options)?;
+        let response = self.execute(reqwest::Method::POST, "/messages", &query, Some(body_content)).await?;
+        parse_sent_message(response.text()?)
+    }
+
+    /// the put message request `send_message_with_options` would send, signed and ready but not sent, for when
+    /// your own code does the sending. Send it with a `reqwest::Client` of your own:
+    /// `client.execute(signed.into_request()?)`. There are no retries, and a 2xx body goes through
+    /// `parse_sent_message` to get what `send_message` would have returned.
+    /// it's only good for `SIGNATURE_VALIDITY_SECONDS`, call this again for a new one rather than keeping it.
+    pub async fn build_put_message_request(
+        &self,
+        message_text: &str,
+        options: &SendOptions,
+    ) -> Result<SignedRequest, QueueError> {
+        let body_content = self.message_body(message_text)?;
+        let query = self.put_message_query(options)?;
+        self.sign_for_later(reqwest::Method::POST, "/messages", &query, body_content).await
+    }
+
+    /// the query for a put message, with the client's defaults filled in and checked
+    fn put_message_query(&self, options: &SendOptions) -> Result<Vec<(&'static str, String)>, QueueError> {
+        let mut query = Vec::<(&str, String)>::new();
+        if let Some(ttl) = options.message_ttl.or(self.default_message_ttl) {
+            validate_message_ttl(ttl, &self.api_version)?;
+            query.push(("messagettl", ttl.to_string()));
+        }
+        if let Some(timeout) = options.initial_visibility.or(self.default_visibility_timeout) {
+            validate_put_visibility_timeout(timeout)?;
+            query.push(("visibilitytimeout", timeout.to_string()));
+        }
+        Ok(query)
+    }
+
+    /// read up to `num` (1-32) messages off the front of the queue.
+    /// they become invisible to everyone else for `visibility_timeout` seconds (azure defaults to 30 if `None`),
+    /// after which they reappear unless you've deleted them.
+    /// an empty queue is an empty vec, not an error.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/get-messages
+    pub async fn get_messages(&self, num: u32, visibility_timeout: Option<u32>) -> Result<Vec<QueueMessage>, QueueError> {
+        let options = ReceiveOptions {
+            num_messages: Some(num),
+            visibility_timeout,
+        };
+        self.receive_messages(&options).await
+    }
+
+    /// `get_messages` with everything optional, whatever's left out is azure's default. Each message comes with
+    /// its pop receipt exactly as azure sent it, that's what `delete_message` and `update_message` want back.
+    /// a message that isn't in the client's `MessageEncoding` is a `QueueError::Decode` carrying that message.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/get-messages
+    pub async fn receive_messages(&self, options: &ReceiveOptions) -> Result<Vec<QueueMessage>, QueueError> {
+        self.receive_each_message(options).await?.into_iter().collect()
+    }
+
+    /// `receive_messages` with a result per message, see `parse_each_message`.
+    async fn receive_each_message(
+        &self,
+        options: &ReceiveOptions,
+    ) -> Result<Vec<Result<QueueMessage, QueueError>>, QueueError> {
+        let mut query = Vec::<(&str, String)>::new();
+        if let Some(num) = options.num_messages {
+            validate_num_messages(num)?;
+            query.push(("numofmessages", num.to_string()));
+        }
+        if let Some(timeout) = options.visibility_timeout {
+            if timeout == 0 || timeout > MAX_SECONDS {
+                return Err(QueueError::InvalidConfig(format!(
+                    "visibility timeout must be between 1 and {} seconds, got {}",
+                    MAX_SECONDS, timeout
+                )));
+            }
+            query.push(("visibilitytimeout", timeout.to_string()));
+        }
+        let response = self.execute(reqwest::Method::GET, "/messages", &query, None).await?;
+        parse_each_message(response.text()?, self.message_encoding)
+    }
+
+    /// look at up to `num` (1-32) messages at the front of the queue without taking them, they stay visible to
+    /// everyone else. Handy for monitoring what's sitting in a queue.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/peek-messages
+    pub async fn peek_messages(&self, num: u32) -> Result<Vec<PeekedMessage>, QueueError> {
+        validate_num_messages(num)?;
+        let query = [("peekonly", "true".to_string()), ("numofmessages", num.to_string())];
+        let response = self.execute(reqwest::Method::GET, "/messages", &query, None).await?;
+        parse_peeked_messages_list(response.text()?, self.message_encoding)
+    }
+
+    /// delete a message you've finished with, using the id and pop receipt `get_messages` gave you.
+    /// the pop receipt is only good until the message becomes visible again (or someone else gets it),
+    /// after that azure will say no, which comes back as `QueueError::PopReceiptMismatch`.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/delete-message2
+    pub async fn delete_message(&self, message_id: &str, pop_receipt: &str) -> Result<(), QueueError> {
+        // pop receipts are base64-ish so full of + and =, reqwest url encodes it on the way out and the
+        // signature wants the plain value, which is what we pass.
+        let query = [("popreceipt", pop_receipt.to_string())];
+        let path = format!("/messages/{}", message_id);
+        self.execute(reqwest::Method::DELETE, &path, &query, None)
+            .await
+            .map_err(|e| pop_receipt_mismatch(message_id, e))?;
+        Ok(())
+    }
+
+    /// the usual consumer loop in one call: get up to `batch` messages hidden for `visibility` seconds, hand
+    /// each to `handler` in turn, and delete the ones it returns `Ok` for. A message the handler fails on is left
+    /// alone and shows up again once `visibility` runs out, so a handler that fails halfway has to be safe to
+    /// run again (at least once delivery). Give it enough `visibility` to get through the whole batch.
+    /// returns how many messages were handled and deleted, 0 when the queue was empty.
+    /// handler errors are only logged, a failed get or delete stops the batch and comes back as the error.
+    /// a message that doesn't decode never gets to the handler, it's logged and left to come back, the same as
+    /// a handler error. Something else has to delete it or move it to a dead letter queue.
+    pub async fn process_messages<F, Fut, E>(
+        &self,
+        batch: u32,
+        visibility: u32,
+        mut handler: F,
+    ) -> Result<usize, QueueError>
+    where
+        F: FnMut(QueueMessage) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: fmt::Display,
+    {
+        let (_, processed) = self.process_batch(batch, visibility, &mut handler).await?;
+        Ok(processed)
+    }
+
+    /// `process_messages`, also saying how many messages there were, as opposed to how many went well.
+    pub(crate) async fn process_batch<F, Fut, E>(
+        &self,
+        batch: u32,
+        visibility: u32,
+        handler: &mut F,
+    ) -> Result<(usize, usize), QueueError>
+    where
+        F: FnMut(QueueMessage) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: fmt::Display,
+    {
+        let options = ReceiveOptions {
+            num_messages: Some(batch),
+            visibility_timeout: Some(visibility),
+        };
+        let messages = self.receive_each_message(&options).await?;
+        let received = messages.len();
+        let mut processed = 0;
+        for message in messages {
+            let message = match message {
+                Ok(message) => message,
+                // the handler can't do anything with it either, but the rest of the batch is fine
+                Err(e) => {
+                    log::warn!(
+                        "skipping a message on queue {} that doesn't decode, delete it or move it to a dead letter \
+                         queue: {}",
+                        self.queue_name(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            let (message_id, pop_receipt) = (message.message_id.clone(), message.pop_receipt.clone());
+            match handler(message).await {
+                Ok(()) => match self.delete_message(&message_id, &pop_receipt).await {
+                    Ok(()) => processed += 1,
+                    // it outlived its visibility while the handler ran and someone else has it now, no reason
+                    // to stop the rest of the batch over that
+                    Err(e @ QueueError::PopReceiptMismatch { .. }) => log::warn!(
+                        "couldn't delete message {} from queue {} after handling it, it'll be handled again: {}",
+                        message_id,
+                        self.queue_name(),
+                        e
+                    ),
+                    Err(e) => return Err(e),
+                },
+                Err(e) => log::warn!(
+                    "handler failed on message {} from queue {}, it'll be back after the visibility timeout: {}",
+                    message_id,
+                    self.queue_name(),
+                    e
+                ),
+            }
+        }
+        Ok((received, processed))
+    }
+
+    /// delete every message in the queue in one go, for test teardown and the like.
+    /// azure answers 204 No Content rather than the 201 put gives you.
+    /// on a big queue azure gives up after 30 seconds with a 500 OperationTimedOut, having deleted some of it,
+    /// and wants you to call again. That's done here, up to `MAX_CLEAR_CALLS` times, so `Ok` means it's empty.
+    /// the client's `RetryPolicy` still applies to other transient failures, but those retries come out of the
+    /// same `MAX_CLEAR_CALLS`, so one clear never sends more requests than that.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/clear-messages
+    pub async fn clear_messages(&self) -> Result<(), QueueError> {
+        // OperationTimedOut is a 500, which the policy would retry too and every one of its retries would then
+        // be multiplied by the calls here. So each call is a single request and this loop does all the retrying.
+        let mut client = self.clone();
+        client.options.retry.max_retries = 0;
+        let mut calls = 0;
+        let mut retries = 0;
+        loop {
+            calls += 1;
+            match client.execute(reqwest::Method::DELETE, "/messages", &[], None).await {
+                Err(e) if e.error_code() == Some("OperationTimedOut") && calls < MAX_CLEAR_CALLS => log::info!(
+                    "clearing queue {} timed out part way through, carrying on (call {})",
+                    self.queue_name(),
+                    calls
+                ),
+                Err(e) if is_transient(&e) && retries < self.options.retry.max_retries && calls < MAX_CLEAR_CALLS => {
+                    retries += 1;
+                    tokio::time::sleep(self.options.retry.delay(retries, retry_after(&e))).await;
+                }
+                result => return result.map(|_| ()),
+            }
+        }
+    }
+
+    /// change how long a message you're holding stays invisible, and optionally its text, e.g. to extend the
+    /// lease on something that's taking a while to process. `visibility_timeout` is from now, 0 puts it straight
+    /// back on the queue. `None` for `new_text` leaves the text as it was.
+    /// the pop receipt you passed in is dead once this works, the one in the result is the one to use next
+    /// (`QueueMessage::update` keeps track of that for you).
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/update-message
+    pub async fn update_message(
+        &self,
+        message_id: &str,
+        pop_receipt: &str,
+        visibility_timeout: u32,
+        new_text: Option<String>,
+    ) -> Result<UpdateResult, QueueError> {
+        if visibility_timeout > MAX_SECONDS {
+            return Err(QueueError::InvalidConfig(format!(
+                "visibility timeout must be at most {} seconds, got {}",
+                MAX_SECONDS, visibility_timeout
+            )));
+        }
+        let query = [
+            ("popreceipt", pop_receipt.to_string()),
+            ("visibilitytimeout", visibility_timeout.to_string()),
+        ];
+        let path = format!("/messages/{}", message_id);
+        // no new text is an empty body, which execute signs with an empty content-length like any other
+        let body = new_text.map(|text| self.message_body(&text)).transpose()?;
+        let response = self
+            .execute(reqwest::Method::PUT, &path, &query, body)
+            .await
+            .map_err(|e| pop_receipt_mismatch(message_id, e))?;
+        Ok(UpdateResult {
+            pop_receipt: required_header(&response, "x-ms-popreceipt")?.to_string(),
+            time_next_visible: parse_time(
+                "x-ms-time-next-visible",
+                required_header(&response, "x-ms-time-next-visible")?,
+            )?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection_string::EMULATOR_ACCOUNT_KEY;
+    use crate::tests::{all_requests, request_header, serve, server_signature};
+    use crate::{QueueClientBuilder, DEFAULT_API_VERSION};
+
+    /// one `<QueueMessage>` of a get messages response with `text` as the message text
+    fn get_message(id: &str, text: &str) -> String {
+        format!(
+            "<QueueMessage><MessageId>{}</MessageId>\
+             <InsertionTime>Fri, 09 Oct 2009 21:04:30 GMT</InsertionTime>\
+             <ExpirationTime>Fri, 16 Oct 2009 21:04:30 GMT</ExpirationTime>\
+             <PopReceipt>receipt-{}</PopReceipt>\
+             <TimeNextVisible>Fri, 09 Oct 2009 23:29:20 GMT</TimeNextVisible>\
+             <DequeueCount>1</DequeueCount><MessageText>{}</MessageText></QueueMessage>",
+            id, id, text
+        )
+    }
+
+    fn messages_list(messages: &[String]) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?><QueueMessagesList>{}</QueueMessagesList>",
+            messages.join("")
+        )
+    }
+
+    #[test]
+    fn message_that_does_not_decode_keeps_its_id_and_pop_receipt() {
+        let body = messages_list(&[get_message("good", "aGVsbG8="), get_message("bad", "not base64!")]);
+
+        let each = parse_each_message(&body, MessageEncoding::Base64).unwrap();
+        assert_eq!(each.len(), 2);
+        assert_eq!(each[0].as_ref().unwrap().message_text, "hello");
+        match &each[1] {
+            Err(QueueError::Decode { message, .. }) => {
+                assert_eq!(message.message_id, "bad");
+                assert_eq!(message.pop_receipt, "receipt-bad");
+                assert_eq!(message.message_text, "not base64!");
+            }
+            other => panic!("expected a decode error, got {:?}", other),
+        }
+
+        match parse_messages_list(&body, MessageEncoding::Base64) {
+            Err(QueueError::Decode { message, .. }) => assert_eq!(message.message_id, "bad"),
+            other => panic!("expected a decode error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn message_text_is_escaped_in_the_request_body() {
+        let body = create_content_string("a & b < c");
+        assert_eq!(body, "<QueueMessage>\n<MessageText>a &amp; b &lt; c</MessageText>\n</QueueMessage>");
+        // nothing left in the text that an xml parser would trip over, and it reads back as what was sent
+        let text = xml::element(&body, "MessageText").unwrap();
+        assert!(!text.contains('<') && !text.replace("&amp;", "").replace("&lt;", "").contains('&'));
+        assert_eq!(xml::unescape(text), "a & b < c");
+    }
+
+    #[tokio::test]
+    async fn signed_and_sent_lengths_agree_for_multi_byte_text() {
+        // 2 and 4 byte characters, sent as is the char count and the byte count of the body are different.
+        // base64'd the body is all ascii, but it's base64 of the 11 bytes, not of 7 characters
+        let text = "héllo 🦀";
+        for encoding in [MessageEncoding::None, MessageEncoding::Base64] {
+            let client =
+                QueueClientBuilder::new().emulator().queue_name("lengths").message_encoding(encoding).build().unwrap();
+            let body = client.message_body(text).unwrap();
+            match encoding {
+                MessageEncoding::None => assert_ne!(body.len(), body.chars().count()),
+                MessageEncoding::Base64 => assert!(body.contains("aMOpbGxvIPCfpoA=")),
+            }
+            let details = client.dry_run(reqwest::Method::POST, "/messages", &[], Some(body.clone())).await.unwrap();
+
+            let sent: usize = details.request.headers()["content-length"].to_str().unwrap().parse().unwrap();
+            let string_to_sign = details.string_to_sign.unwrap();
+            // verb, content encoding, content language, then content length
+            let signed: usize = string_to_sign.split('\n').nth(3).unwrap().parse().unwrap();
+            let actual = details.request.body().and_then(|body| body.as_bytes()).unwrap().len();
+            assert_eq!(sent, body.len());
+            assert_eq!(signed, sent);
+            assert_eq!(actual, sent);
+        }
+    }
+
+    #[test]
+    fn message_size_limit_is_checked_before_sending() {
+        let client = |encoding, api_version| {
+            QueueClientBuilder::new()
+                .emulator()
+                .queue_name("sizes")
+                .message_encoding(encoding)
+                .api_version(api_version)
+                .build()
+                .unwrap()
+        };
+        let too_big = |result: Result<String, QueueError>| match result {
+            Err(QueueError::InvalidConfig(message)) => message,
+            other => panic!("expected InvalidConfig, got {:?}", other),
+        };
+
+        // sent as is, 65536 bytes is the most
+        let raw = client(MessageEncoding::None, DEFAULT_API_VERSION);
+        assert!(raw.message_body(&"a".repeat(65535)).is_ok());
+        assert!(raw.message_body(&"a".repeat(65536)).is_ok());
+        assert_eq!(too_big(raw.message_body(&"a".repeat(65537))), "message is 65537 bytes, azure takes at most 65536");
+        // it's bytes that count, 32768 two byte characters is exactly the limit
+        assert!(raw.message_body(&"é".repeat(32768)).is_ok());
+        assert!(raw.message_body(&format!("{}a", "é".repeat(32768))).is_err());
+
+        // base64'd, 49152 bytes encodes to exactly 65536 and one more byte takes it to 65540
+        let base64 = client(MessageEncoding::Base64, DEFAULT_API_VERSION);
+        assert!(base64.message_body(&"a".repeat(49152)).is_ok());
+        assert_eq!(
+            too_big(base64.message_body(&"a".repeat(49153))),
+            "message is 65540 bytes once base64 encoded, azure takes at most 65536 (49152 before encoding)"
+        );
+
+        // 8 KB before 2011-08-18
+        let old = client(MessageEncoding::None, "2009-09-19");
+        assert!(old.message_body(&"a".repeat(8192)).is_ok());
+        assert!(old.message_body(&"a".repeat(8193)).is_err());
+    }
+
+    /// a put message response the way azure sends it, no DequeueCount or MessageText
+    const PUT_MESSAGE_RESPONSE: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?><QueueMessagesList><QueueMessage>\
+        <MessageId>5974b586-0df3-4e2d-ad0c-18e3892bfca2</MessageId>\
+        <InsertionTime>Fri, 09 Oct 2009 21:04:30 GMT</InsertionTime>\
+        <ExpirationTime>Fri, 16 Oct 2009 21:04:30 GMT</ExpirationTime>\
+        <PopReceipt>YzQ4Yzg1MDItYTc0Ny00OWNjLTkxYTUtZGM0MDFiZDAwYzEw</PopReceipt>\
+        <TimeNextVisible>Fri, 09 Oct 2009 23:29:20 GMT</TimeNextVisible>\
+        </QueueMessage></QueueMessagesList>";
+
+    #[tokio::test]
+    async fn put_message_response_is_parsed() {
+        let check = |sent: SentMessage| {
+            assert_eq!(sent.message_id, "5974b586-0df3-4e2d-ad0c-18e3892bfca2");
+            assert_eq!(sent.pop_receipt, "YzQ4Yzg1MDItYTc0Ny00OWNjLTkxYTUtZGM0MDFiZDAwYzEw");
+            assert_eq!(sent.insertion_time, DateTime::parse_from_rfc3339("2009-10-09T21:04:30Z").unwrap());
+            assert_eq!(sent.expiration_time, DateTime::parse_from_rfc3339("2009-10-16T21:04:30Z").unwrap());
+            assert_eq!(sent.time_next_visible, DateTime::parse_from_rfc3339("2009-10-09T23:29:20Z").unwrap());
+        };
+        check(parse_sent_message(PUT_MESSAGE_RESPONSE).unwrap());
+
+        // and what send_message hands back from it
+        let (endpoint, _requests) = serve(vec![("201 Created", String::new(), PUT_MESSAGE_RESPONSE.to_string())]).await;
+        let client = QueueClientBuilder::new().emulator().endpoint(&endpoint).queue_name("sent").build().unwrap();
+        check(client.send_message("hello".to_string()).await.unwrap());
+
+        assert!(matches!(parse_sent_message("<QueueMessagesList />"), Err(QueueError::InvalidResponse(_))));
+    }
+
+    #[test]
+    fn empty_and_several_message_lists() {
+        for empty in ["<QueueMessagesList />", &messages_list(&[]), ""] {
+            assert!(parse_messages_list(empty, MessageEncoding::Base64).unwrap().is_empty(), "{}", empty);
+        }
+
+        let body = messages_list(&[
+            get_message("one", "Zmlyc3Q="),
+            get_message("two", "c2Vjb25k"),
+            get_message("three", "dGhpcmQ="),
+        ]);
+        let messages = parse_messages_list(&body, MessageEncoding::Base64).unwrap();
+        let ids: Vec<&str> = messages.iter().map(|message| message.message_id.as_str()).collect();
+        let texts: Vec<&str> = messages.iter().map(|message| message.message_text.as_str()).collect();
+        assert_eq!(ids, vec!["one", "two", "three"]);
+        assert_eq!(texts, vec!["first", "second", "third"]);
+        let first = &messages[0];
+        assert_eq!(first.pop_receipt, "receipt-one");
+        assert_eq!(first.dequeue_count, 1);
+        assert_eq!(first.insertion_time, DateTime::parse_from_rfc3339("2009-10-09T21:04:30Z").unwrap());
+        assert_eq!(first.expiration_time, DateTime::parse_from_rfc3339("2009-10-16T21:04:30Z").unwrap());
+        assert_eq!(first.time_next_visible, DateTime::parse_from_rfc3339("2009-10-09T23:29:20Z").unwrap());
+
+        // put message leaves out DequeueCount and MessageText
+        let put = parse_messages_list(PUT_MESSAGE_RESPONSE, MessageEncoding::Base64).unwrap();
+        assert_eq!((put[0].dequeue_count, put[0].message_text.as_str()), (0, ""));
+    }
+
+    #[tokio::test]
+    async fn peeked_text_with_xml_special_characters() {
+        let text = r#"{"html": "<b>fish & chips</b>", "it's": "a > b"}"#;
+        // a peek has no PopReceipt or TimeNextVisible
+        let body = messages_list(&[format!(
+            "<QueueMessage><MessageId>peeked</MessageId>\
+             <InsertionTime>Fri, 09 Oct 2009 21:04:30 GMT</InsertionTime>\
+             <ExpirationTime>Fri, 16 Oct 2009 21:04:30 GMT</ExpirationTime>\
+             <DequeueCount>0</DequeueCount><MessageText>{}</MessageText></QueueMessage>",
+            xml::escape(text)
+        )]);
+        let peeked = parse_peeked_messages_list(&body, MessageEncoding::None).unwrap();
+        assert_eq!(peeked.len(), 1);
+        assert_eq!(peeked[0].message_id, "peeked");
+        assert_eq!(peeked[0].message_text, text);
+
+        let (endpoint, requests) = serve(vec![("200 OK", String::new(), body)]).await;
+        let client = QueueClientBuilder::new()
+            .emulator()
+            .endpoint(&endpoint)
+            .queue_name("peeking")
+            .message_encoding(MessageEncoding::None)
+            .build()
+            .unwrap();
+        let peeked = client.peek_messages(1).await.unwrap();
+        assert_eq!(peeked[0].message_text, text);
+        let request = all_requests(requests).await.remove(0);
+        assert!(request.starts_with("GET /devstoreaccount1/peeking/messages?peekonly=true&numofmessages=1 "));
+    }
+
+    #[tokio::test]
+    async fn update_with_zero_visibility_makes_the_message_visible_again() {
+        let updated = |receipt: &str| {
+            let headers = format!(
+                "x-ms-popreceipt: {}\r\nx-ms-time-next-visible: Fri, 09 Oct 2009 21:04:30 GMT\r\n",
+                receipt
+            );
+            ("204 No Content", headers, String::new())
+        };
+        let (endpoint, requests) = serve(vec![updated("AgAAAAMAAAAAAAAA2"), updated("AgAAAAMAAAAAAAAA3")]).await;
+        let client = QueueClientBuilder::new().emulator().endpoint(&endpoint).queue_name("leases").build().unwrap();
+
+        // a pop receipt with characters that have to be url encoded, they're signed decoded
+        let result = client.update_message("msg-1", "AgAAAAMAAAAAAAAA+1/=", 0, None).await.unwrap();
+        assert_eq!(result.pop_receipt, "AgAAAAMAAAAAAAAA2");
+        assert_eq!(result.time_next_visible, DateTime::parse_from_rfc3339("2009-10-09T21:04:30Z").unwrap());
+        // and again with the new receipt and new text
+        client.update_message("msg-1", &result.pop_receipt, 0, Some("retry 2".to_string())).await.unwrap();
+
+        let seen = all_requests(requests).await;
+        assert!(seen[0].starts_with(
+            "PUT /devstoreaccount1/leases/messages/msg-1?popreceipt=AgAAAAMAAAAAAAAA%2B1%2F%3D&visibilitytimeout=0 "
+        ));
+        assert_eq!(request_header(&seen[0], "content-length").as_deref(), Some("0"));
+        assert!(seen[1].contains("visibilitytimeout=0"));
+        assert!(seen[1].ends_with(&client.message_body("retry 2").unwrap()));
+        let message = "/devstoreaccount1/devstoreaccount1/leases/messages/msg-1";
+        for (request, receipt) in seen.iter().zip(["AgAAAAMAAAAAAAAA+1/=", "AgAAAAMAAAAAAAAA2"]) {
+            let resource = format!("{}\npopreceipt:{}\nvisibilitytimeout:0", message, receipt);
+            let signature = server_signature(request, EMULATOR_ACCOUNT_KEY, &resource);
+            let authorization = request_header(request, "authorization").unwrap();
+            assert_eq!(authorization, format!("SharedKey devstoreaccount1:{}", signature));
+        }
+    }
+}
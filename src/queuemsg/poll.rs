@@ -0,0 +1,88 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{QueueClient, QueueError, QueueMessage};
+
+/// how often a sleeping `poll_loop` looks at the shutdown flag, so stopping doesn't wait out a long backoff.
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// settings for `poll_loop`. `Default` gives batches of 32 hidden for 30 seconds, polling an empty queue
+/// somewhere between every second and every 30 seconds:
+/// `let mut options = PollOptions::default(); options.shutdown = Some(stop.clone());`
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct PollOptions {
+    /// messages per get, 1-32
+    pub batch: u32,
+    /// seconds each batch is hidden for, it needs to cover handling the whole batch
+    pub visibility: u32,
+    /// the wait after the first empty get. It's also the wait once messages turn up again.
+    pub min_interval: Duration,
+    /// each empty get in a row doubles the wait, up to this
+    pub max_interval: Duration,
+    /// set it to true and the loop returns once the message it's on is done. Nothing is cut off halfway.
+    pub shutdown: Option<Arc<AtomicBool>>,
+}
+
+impl Default for PollOptions {
+    fn default() -> Self {
+        PollOptions {
+            batch: 32,
+            visibility: 30,
+            min_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(30),
+            shutdown: None,
+        }
+    }
+}
+
+impl PollOptions {
+    fn stopping(&self) -> bool {
+        self.shutdown.as_ref().is_some_and(|shutdown| shutdown.load(Ordering::SeqCst))
+    }
+}
+
+impl QueueClient {
+    /// a queue worker: `process_messages` over and over until `options.shutdown` is set. While the queue is
+    /// empty it backs off from `min_interval` to `max_interval`, so an idle worker isn't paying for a get every
+    /// second (every get is a billed transaction), and goes straight back to full speed once there's something
+    /// to do. Messages keep coming without any waiting as long as the gets aren't empty.
+    /// handler errors are logged and the message left to come back, same as `process_messages`. An error
+    /// talking to azure that's still failing after the retries ends the loop and comes back here.
+    pub async fn poll_loop<F, Fut, E>(&self, options: PollOptions, mut handler: F) -> Result<(), QueueError>
+    where
+        F: FnMut(QueueMessage) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: fmt::Display,
+    {
+        if options.min_interval.is_zero() || options.min_interval > options.max_interval {
+            return Err(QueueError::InvalidConfig(format!(
+                "poll min_interval must be more than zero and no more than max_interval, got {:?} and {:?}",
+                options.min_interval, options.max_interval
+            )));
+        }
+        let mut interval = options.min_interval;
+        while !options.stopping() {
+            let (received, _) = self.process_batch(options.batch, options.visibility, &mut handler).await?;
+            if received > 0 {
+                interval = options.min_interval;
+                continue;
+            }
+            sleep_unless_stopped(interval, &options).await;
+            interval = interval.saturating_mul(2).min(options.max_interval);
+        }
+        Ok(())
+    }
+}
+
+async fn sleep_unless_stopped(duration: Duration, options: &PollOptions) {
+    let mut remaining = duration;
+    while !remaining.is_zero() && !options.stopping() {
+        let step = remaining.min(SHUTDOWN_CHECK_INTERVAL);
+        tokio::time::sleep(step).await;
+        remaining -= step;
+    }
+}
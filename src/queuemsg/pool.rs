@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::builder::validate_options;
+use crate::{BuildError, ClientOptions, QueueClient, QueueError, SentMessage};
+
+/// picks which client in a `QueuePool` a partition key goes to, by logical name.
+pub type Router = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// a handful of `QueueClient`s (usually in different storage accounts) under logical names, so fanning out
+/// across accounts doesn't mean juggling a client per account. Clients added share the pool's one http
+/// connection pool, apart from ones built with their own transport (`.http_client()`, a proxy or root
+/// certificates), which keep it.
+/// `send_routed` spreads messages across the clients by partition key, the same key always lands on the
+/// same client as long as the set of clients doesn't change.
+#[derive(Clone)]
+pub struct QueuePool {
+    clients: HashMap<String, QueueClient>,
+    /// names in the order they were added, so the default routing doesn't depend on hashmap order
+    names: Vec<String>,
+    router: Option<Router>,
+    http: reqwest::Client,
+}
+
+/// why a `QueuePool` call failed. The request errors say which client, account and queue it was aimed at.
+#[derive(Debug)]
+pub enum PoolError {
+    /// there's no client under that logical name (or the router came up with one that doesn't exist).
+    UnknownClient(String),
+    /// `send_routed` on a pool with nothing in it.
+    Empty,
+    /// the request itself failed.
+    Request {
+        name: String,
+        account_name: String,
+        queue_name: String,
+        error: Box<QueueError>,
+    },
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::UnknownClient(name) => write!(f, "no queue client called '{}' in the pool", name),
+            PoolError::Empty => write!(f, "the queue pool has no clients in it"),
+            PoolError::Request { name, account_name, queue_name, error } => write!(
+                f,
+                "request to '{}' (account {}, queue {}) failed: {}",
+                name, account_name, queue_name, error
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PoolError::Request { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for QueuePool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueuePool")
+            .field("clients", &self.clients)
+            .field("names", &self.names)
+            .field("router", &self.router.as_ref().map(|_| "<fn>"))
+            .finish()
+    }
+}
+
+impl QueuePool {
+    /// an empty pool. The connection pool's timeouts and user agent come from `options`, `ClientOptions::default()`
+    /// is fine for most; each client keeps its own retry settings.
+    pub fn new(options: ClientOptions) -> Result<QueuePool, BuildError> {
+        validate_options(&options)?;
+        // the pool's connections go to whatever accounts get added, so there's no telling it's only an emulator
+        if options.danger_accept_invalid_certs {
+            return Err(BuildError::Conflict(
+                "a pool can't accept invalid certificates, build the emulator client with it instead",
+            ));
+        }
+        Ok(QueuePool {
+            clients: HashMap::new(),
+            names: Vec::new(),
+            router: None,
+            http: options.http_client()?,
+        })
+    }
+
+    /// add a client under a logical name, e.g. "orders-east". Adding a name that's already there replaces
+    /// the client but keeps its place in the routing order.
+    pub fn add(mut self, name: &str, mut client: QueueClient) -> QueuePool {
+        // swapping the http client would quietly drop a proxy or certificates it was built with
+        if !client.own_transport {
+            client.http = self.http.clone();
+        }
+        if self.clients.insert(name.to_string(), client).is_none() {
+            self.names.push(name.to_string());
+        }
+        self
+    }
+
+    /// decide yourself which client a partition key goes to, e.g. tenant id to the account that tenant lives in.
+    /// return the logical name it was added under. Without one, keys are hashed across the clients in the order
+    /// they were added.
+    pub fn router<F>(mut self, router: F) -> QueuePool
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.router = Some(Arc::new(router));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&QueueClient> {
+        self.clients.get(name)
+    }
+
+    /// every client for a storage account, in the order they were added. There's one per queue, so an account
+    /// can have several.
+    pub fn get_by_account(&self, account_name: &str) -> Vec<&QueueClient> {
+        self.names
+            .iter()
+            .filter_map(|name| self.clients.get(name))
+            .filter(|client| client.account_name() == account_name)
+            .collect()
+    }
+
+    /// the client for a queue by its url, e.g. `https://myaccount.queue.core.windows.net/orders`. The
+    /// `/messages` url from `QueueClient::queue_url` works too, and a query string (a SAS) is ignored.
+    pub fn get_by_url(&self, queue_url: &str) -> Option<&QueueClient> {
+        let queue_url = queue_url.split('?').next().unwrap_or_default().trim_end_matches('/');
+        let queue_url = queue_url.strip_suffix("/messages").unwrap_or(queue_url);
+        self.names
+            .iter()
+            .filter_map(|name| self.clients.get(name))
+            .find(|client| client.resource_url("").eq_ignore_ascii_case(queue_url))
+    }
+
+    /// logical names in the order they were added.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// which client `send_routed` would use for this key.
+    pub fn route(&self, partition_key: &str) -> Result<&str, PoolError> {
+        if self.names.is_empty() {
+            return Err(PoolError::Empty);
+        }
+        let name = match &self.router {
+            Some(router) => router(partition_key),
+            None => self.names[(fnv1a(partition_key) % self.names.len() as u64) as usize].clone(),
+        };
+        match self.clients.get_key_value(&name) {
+            Some((name, _)) => Ok(name),
+            None => Err(PoolError::UnknownClient(name)),
+        }
+    }
+
+    /// put a message on the queue of the client called `name`.
+    pub async fn send_to(&self, name: &str, message_text: String) -> Result<SentMessage, PoolError> {
+        let client = self
+            .clients
+            .get(name)
+            .ok_or_else(|| PoolError::UnknownClient(name.to_string()))?;
+        client.send_message(message_text).await.map_err(|error| PoolError::Request {
+            name: name.to_string(),
+            account_name: client.account_name().to_string(),
+            queue_name: client.queue_name().to_string(),
+            error: Box::new(error),
+        })
+    }
+
+    /// put a message on whichever client `partition_key` routes to.
+    pub async fn send_routed(&self, partition_key: &str, message_text: String) -> Result<SentMessage, PoolError> {
+        let name = self.route(partition_key)?;
+        self.send_to(name, message_text).await
+    }
+}
+
+/// std's hasher is allowed to change between rust versions, and routing has to stay put between deploys,
+/// so this is plain 64 bit FNV-1a.
+fn fnv1a(key: &str) -> u64 {
+    key.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use futures::future::BoxFuture;
+use tokio::sync::Mutex;
+
+use crate::{Authorization, Credential, QueueError, RequestParts, SasCredential};
+
+type SasSource = Arc<dyn Fn() -> BoxFuture<'static, Result<(String, DateTime<Utc>), QueueError>> + Send + Sync>;
+type RenewalListener = Arc<dyn Fn(&SasRenewal) + Send + Sync>;
+type CachedSas = Option<(SasCredential, DateTime<Utc>)>;
+
+/// how close to expiry a SAS token gets before we go and get the next one, unless you say otherwise.
+const DEFAULT_RENEWAL_MARGIN_MINUTES: i64 = 5;
+
+/// why `RenewingSasCredential` went and got a new token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenewalReason {
+    /// the first request, there was nothing cached yet
+    Initial,
+    /// the cached token was within the renewal margin of expiring
+    Expiring,
+    /// azure refused the last one with 403 AuthenticationFailed, e.g. it was revoked or rotated early
+    AuthenticationFailed,
+}
+
+/// handed to the `on_renewal` listener each time a new token has been fetched.
+#[derive(Clone, Debug)]
+pub struct SasRenewal {
+    pub reason: RenewalReason,
+    /// when the new token says it expires
+    pub expires_on: DateTime<Utc>,
+}
+
+/// SAS tokens that get rotated, e.g. short lived ones from a secrets service. Give it an async function that
+/// returns a token (anything `SasCredential::new` takes) and when it expires, it's called again once the
+/// token is within `renewal_margin` of expiring. If azure answers 403 AuthenticationFailed anyway the token is
+/// thrown away and the request tried once more with a fresh one.
+/// concurrent sends that find the token stale wait on one call to the source rather than each making their own.
+/// hand it to `QueueClientBuilder::credential`.
+#[derive(Clone)]
+pub struct RenewingSasCredential {
+    source: SasSource,
+    margin: Duration,
+    listener: Option<RenewalListener>,
+    cached: Arc<Mutex<CachedSas>>,
+    /// set when azure rejected the cached token, so the next request fetches a new one whatever its expiry says
+    rejected: Arc<AtomicBool>,
+}
+
+impl RenewingSasCredential {
+    /// `source` is e.g. `|| async { Ok((fetch_sas().await?, expiry)) }`
+    pub fn new<F, Fut>(source: F) -> RenewingSasCredential
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(String, DateTime<Utc>), QueueError>> + Send + 'static,
+    {
+        RenewingSasCredential {
+            source: Arc::new(move || Box::pin(source())),
+            margin: Duration::minutes(DEFAULT_RENEWAL_MARGIN_MINUTES),
+            listener: None,
+            cached: Arc::new(Mutex::new(None)),
+            rejected: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// renew this long before the token expires, 5 minutes if you don't say.
+    pub fn renewal_margin(mut self, margin: std::time::Duration) -> RenewingSasCredential {
+        self.margin = Duration::from_std(margin).unwrap_or(Duration::MAX);
+        self
+    }
+
+    /// called after every renewal, for logging/metrics. Keep it quick, it runs while other requests wait on
+    /// the new token.
+    pub fn on_renewal<F>(mut self, listener: F) -> RenewingSasCredential
+    where
+        F: Fn(&SasRenewal) + Send + Sync + 'static,
+    {
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// the current token, renewing it first if needed. The lock is held across the renewal, that's what stops
+    /// a burst of sends all calling the source.
+    async fn current(&self) -> Result<SasCredential, QueueError> {
+        let mut cached = self.cached.lock().await;
+        let rejected = self.rejected.swap(false, Ordering::SeqCst);
+        let reason = match (cached.as_ref(), rejected) {
+            (None, _) => RenewalReason::Initial,
+            (Some(_), true) => RenewalReason::AuthenticationFailed,
+            (Some((sas, expires_on)), false) => match *expires_on - self.margin <= Utc::now() {
+                true => RenewalReason::Expiring,
+                false => return Ok(sas.clone()),
+            },
+        };
+        let (token, expires_on) = (self.source)().await?;
+        let sas = SasCredential::new(&token)?;
+        *cached = Some((sas.clone(), expires_on));
+        log::debug!("renewed SAS token ({:?}), expires {}", reason, expires_on);
+        if let Some(listener) = &self.listener {
+            listener(&SasRenewal { reason, expires_on });
+        }
+        Ok(sas)
+    }
+}
+
+impl fmt::Debug for RenewingSasCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RenewingSasCredential").field("margin", &self.margin).finish_non_exhaustive()
+    }
+}
+
+impl Credential for RenewingSasCredential {
+    fn authorize<'a>(&'a self, request: &'a RequestParts<'a>) -> BoxFuture<'a, Result<Authorization, QueueError>> {
+        Box::pin(async move { self.current().await?.authorize(request).await })
+    }
+
+    fn sends_date(&self) -> bool {
+        false
+    }
+
+    fn authentication_failed(&self) -> bool {
+        self.rejected.store(true, Ordering::SeqCst);
+        true
+    }
+}
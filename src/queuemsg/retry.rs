@@ -0,0 +1,68 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// how requests that failed with something transient (500/503 from azure, connection problems, timeouts) are
+/// retried. 4xx errors like 403 or 404 are never retried, trying again won't change the answer.
+/// When azure sends a `Retry-After` we wait that long, otherwise the wait doubles each time starting at
+/// `base_delay`, with some jitter so a fleet of clients that got throttled together don't all come back together.
+/// Heads up: put message isn't strictly idempotent. If the connection drops after azure has stored the message
+/// the retry stores it again, so consumers should cope with the odd duplicate (they have to anyway, queues are
+/// at least once). Set `max_retries` to 0 if that's worse for you than the failure.
+/// https://learn.microsoft.com/en-us/azure/storage/common/storage-retry-policy
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RetryPolicy {
+    /// how many times to retry on top of the first attempt. 0 means don't retry.
+    pub max_retries: u32,
+    /// the first retry waits around this long, each one after that around twice as long as the last
+    pub base_delay: Duration,
+    /// no single wait is longer than this, `Retry-After` included
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(800),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, base_delay: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            base_delay,
+            ..Default::default()
+        }
+    }
+
+    /// don't retry anything.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new(0, Duration::ZERO)
+    }
+
+    /// how long to wait before retry number `attempt` (starting at 1). `retry_after` is what the service asked
+    /// for, if anything.
+    pub(crate) fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let delay = match retry_after {
+            Some(retry_after) => retry_after,
+            None => {
+                let backoff = self.base_delay.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+                // somewhere between half and all of the backoff
+                backoff / 2 + backoff.mul_f64(jitter() / 2.0)
+            }
+        };
+        delay.min(self.max_delay)
+    }
+}
+
+/// a number from 0 to 1. RandomState is seeded randomly per instance, which is plenty random for spreading
+/// retries out and saves pulling in a rand crate.
+fn jitter() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,523 @@
+//! minting SAS tokens (account wide or for one queue) from the account key, so something holding the key can
+//! hand out short lived, narrow tokens to things that shouldn't have it. The output goes straight into
+//! `SasCredential::new` or on the end of a queue url.
+//! https://learn.microsoft.com/en-us/rest/api/storageservices/create-account-sas
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+
+use crate::builder::validate_queue_name;
+use crate::{BuildError, QueueClient, QueueError, SharedKeyCredential};
+
+/// the sv we sign with. From 2020-12-06 the string to sign has an encryption scope line on the end.
+const SAS_VERSION: &str = "2021-12-02";
+
+/// which services an account SAS works for. `AccountSasBuilder` starts with just queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccountSasServices {
+    pub blob: bool,
+    pub file: bool,
+    pub queue: bool,
+    pub table: bool,
+}
+
+/// what level of resource an account SAS works on. For queues, `service` is listing queues, `container` is
+/// creating/deleting queues and metadata, `object` is the messages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccountSasResourceTypes {
+    pub service: bool,
+    pub container: bool,
+    pub object: bool,
+}
+
+/// what an account SAS lets you do. For messages `add` is send, `process` is get + delete, `update` is
+/// update and `read` is peek.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccountSasPermissions {
+    pub read: bool,
+    pub write: bool,
+    pub delete: bool,
+    pub list: bool,
+    pub add: bool,
+    pub create: bool,
+    pub update: bool,
+    pub process: bool,
+}
+
+/// which protocols a SAS can be used over.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SasProtocol {
+    #[default]
+    Https,
+    HttpsAndHttp,
+}
+
+/// picks out the letters for the flags that are set, in the order given (azure cares about the order).
+fn flags(set: &[(bool, char)]) -> String {
+    set.iter().filter(|(on, _)| *on).map(|(_, letter)| *letter).collect()
+}
+
+impl fmt::Display for AccountSasServices {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&flags(&[(self.blob, 'b'), (self.file, 'f'), (self.queue, 'q'), (self.table, 't')]))
+    }
+}
+
+impl fmt::Display for AccountSasResourceTypes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&flags(&[(self.service, 's'), (self.container, 'c'), (self.object, 'o')]))
+    }
+}
+
+impl fmt::Display for AccountSasPermissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&flags(&[
+            (self.read, 'r'),
+            (self.write, 'w'),
+            (self.delete, 'd'),
+            (self.list, 'l'),
+            (self.add, 'a'),
+            (self.create, 'c'),
+            (self.update, 'u'),
+            (self.process, 'p'),
+        ]))
+    }
+}
+
+impl fmt::Display for SasProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SasProtocol::Https => f.write_str("https"),
+            SasProtocol::HttpsAndHttp => f.write_str("https,http"),
+        }
+    }
+}
+
+/// SAS times are ISO 8601 in UTC without fractions, e.g. `2024-01-31T12:00:00Z`
+pub(crate) fn format_sas_time(time: &DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// percent encodes the pairs into a query string (no leading `?`), sig included.
+pub(crate) fn encode_query(pairs: &[(&str, String)]) -> String {
+    // borrow reqwest's url to do the encoding, the same way SasCredential borrows it for decoding
+    let mut url = reqwest::Url::parse("https://sas.invalid/").expect("constant url parses");
+    url.query_pairs_mut().extend_pairs(pairs);
+    url.query().unwrap_or_default().to_string()
+}
+
+/// build an account SAS: `AccountSasBuilder::new(expiry).permissions(..).resource_types(..).sign(&credential)`
+#[derive(Clone, Debug)]
+pub struct AccountSasBuilder {
+    services: AccountSasServices,
+    resource_types: AccountSasResourceTypes,
+    permissions: AccountSasPermissions,
+    start: Option<DateTime<Utc>>,
+    expiry: DateTime<Utc>,
+    ip_range: Option<String>,
+    protocol: SasProtocol,
+}
+
+impl AccountSasBuilder {
+    /// a token that stops working at `expiry`. Keep it short, there's no revoking an account SAS short of
+    /// rotating the key.
+    pub fn new(expiry: DateTime<Utc>) -> AccountSasBuilder {
+        AccountSasBuilder {
+            services: AccountSasServices {
+                queue: true,
+                ..Default::default()
+            },
+            resource_types: AccountSasResourceTypes::default(),
+            permissions: AccountSasPermissions::default(),
+            start: None,
+            expiry,
+            ip_range: None,
+            protocol: SasProtocol::default(),
+        }
+    }
+
+    pub fn services(mut self, services: AccountSasServices) -> AccountSasBuilder {
+        self.services = services;
+        self
+    }
+
+    pub fn resource_types(mut self, resource_types: AccountSasResourceTypes) -> AccountSasBuilder {
+        self.resource_types = resource_types;
+        self
+    }
+
+    pub fn permissions(mut self, permissions: AccountSasPermissions) -> AccountSasBuilder {
+        self.permissions = permissions;
+        self
+    }
+
+    /// when the token starts working, leave it out to have it work straight away. If you do set it, set it a
+    /// few minutes in the past, clocks disagree.
+    pub fn start(mut self, start: DateTime<Utc>) -> AccountSasBuilder {
+        self.start = Some(start);
+        self
+    }
+
+    /// a single ip `168.1.5.65` or a range `168.1.5.60-168.1.5.70` the token can be used from.
+    pub fn ip_range(mut self, ip_range: &str) -> AccountSasBuilder {
+        self.ip_range = Some(ip_range.to_string());
+        self
+    }
+
+    pub fn protocol(mut self, protocol: SasProtocol) -> AccountSasBuilder {
+        self.protocol = protocol;
+        self
+    }
+
+    /// sign with the account key and hand back the query string, e.g. `sv=...&ss=q&srt=o&sp=a&se=...&sig=...`
+    pub fn sign(&self, credential: &SharedKeyCredential) -> Result<String, QueueError> {
+        let services = self.services.to_string();
+        let resource_types = self.resource_types.to_string();
+        let permissions = self.permissions.to_string();
+        if services.is_empty() {
+            return Err(BuildError::Missing("SAS services").into());
+        }
+        if resource_types.is_empty() {
+            return Err(BuildError::Missing("SAS resource types").into());
+        }
+        if permissions.is_empty() {
+            return Err(BuildError::Missing("SAS permissions").into());
+        }
+        if self.start.is_some_and(|start| start >= self.expiry) {
+            return Err(BuildError::OutOfRange("SAS start has to be before its expiry".to_string()).into());
+        }
+        let start = self.start.as_ref().map(format_sas_time).unwrap_or_default();
+        let expiry = format_sas_time(&self.expiry);
+        let ip_range = self.ip_range.clone().unwrap_or_default();
+        let protocol = self.protocol.to_string();
+
+        // the empty line before the last newline is the encryption scope, which we don't do
+        let string_to_sign = [
+            credential.account_name(),
+            &permissions,
+            &services,
+            &resource_types,
+            &start,
+            &expiry,
+            &ip_range,
+            &protocol,
+            SAS_VERSION,
+            "",
+            "",
+        ]
+        .join("\n");
+        let signature = credential.sign(&string_to_sign)?;
+
+        let mut query = vec![
+            ("sv", SAS_VERSION.to_string()),
+            ("ss", services),
+            ("srt", resource_types),
+            ("sp", permissions),
+        ];
+        if !start.is_empty() {
+            query.push(("st", start));
+        }
+        query.push(("se", expiry));
+        if !ip_range.is_empty() {
+            query.push(("sip", ip_range));
+        }
+        query.push(("spr", protocol));
+        query.push(("sig", signature));
+        Ok(encode_query(&query))
+    }
+}
+
+/// what a queue SAS lets you do. There's no way to write an invalid one, and the letters come out in the
+/// order azure wants.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueSasPermissions {
+    /// peek messages and read metadata
+    pub read: bool,
+    /// send messages
+    pub add: bool,
+    /// update messages
+    pub update: bool,
+    /// get and delete messages
+    pub process: bool,
+}
+
+impl fmt::Display for QueueSasPermissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&flags(&[(self.read, 'r'), (self.add, 'a'), (self.update, 'u'), (self.process, 'p')]))
+    }
+}
+
+/// the letters, in any order, e.g. `"pa"`. Anything other than r, a, u and p is an error rather than being
+/// dropped, a token quietly missing a permission would only show up as 403s later.
+impl FromStr for QueueSasPermissions {
+    type Err = QueueError;
+
+    fn from_str(permissions: &str) -> Result<Self, Self::Err> {
+        let mut parsed = QueueSasPermissions::default();
+        for letter in permissions.chars() {
+            match letter {
+                'r' => parsed.read = true,
+                'a' => parsed.add = true,
+                'u' => parsed.update = true,
+                'p' => parsed.process = true,
+                other => {
+                    return Err(QueueError::InvalidConfig(format!(
+                        "'{}' isn't a queue SAS permission, they're r, a, u and p",
+                        other
+                    )))
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// build a service SAS for one queue, e.g. add only for an hour for a partner that sends to it:
+/// `QueueSasBuilder::new("orders").permissions(..).expiry(..).sign(&credential)`
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/create-service-sas
+#[derive(Clone, Debug)]
+pub struct QueueSasBuilder {
+    queue_name: String,
+    permissions: QueueSasPermissions,
+    start: Option<DateTime<Utc>>,
+    expiry: Option<DateTime<Utc>>,
+    identifier: Option<String>,
+    ip_range: Option<String>,
+    protocol: SasProtocol,
+}
+
+impl QueueSasBuilder {
+    pub fn new(queue_name: &str) -> QueueSasBuilder {
+        QueueSasBuilder {
+            queue_name: queue_name.to_string(),
+            permissions: QueueSasPermissions::default(),
+            start: None,
+            expiry: None,
+            identifier: None,
+            ip_range: None,
+            protocol: SasProtocol::default(),
+        }
+    }
+
+    /// can be left out if the stored access policy in `identifier` has them.
+    pub fn permissions(mut self, permissions: QueueSasPermissions) -> QueueSasBuilder {
+        self.permissions = permissions;
+        self
+    }
+
+    pub fn start(mut self, start: DateTime<Utc>) -> QueueSasBuilder {
+        self.start = Some(start);
+        self
+    }
+
+    /// can be left out if the stored access policy in `identifier` has one.
+    pub fn expiry(mut self, expiry: DateTime<Utc>) -> QueueSasBuilder {
+        self.expiry = Some(expiry);
+        self
+    }
+
+    /// the id of a stored access policy on the queue. Tokens tied to a policy can be revoked by changing or
+    /// removing the policy, which is the only way to kill a service SAS early short of rotating the key.
+    pub fn identifier(mut self, identifier: &str) -> QueueSasBuilder {
+        self.identifier = Some(identifier.to_string());
+        self
+    }
+
+    /// a single ip `168.1.5.65` or a range `168.1.5.60-168.1.5.70` the token can be used from.
+    pub fn ip_range(mut self, ip_range: &str) -> QueueSasBuilder {
+        self.ip_range = Some(ip_range.to_string());
+        self
+    }
+
+    pub fn protocol(mut self, protocol: SasProtocol) -> QueueSasBuilder {
+        self.protocol = protocol;
+        self
+    }
+
+    /// sign with the account key and hand back the query string, e.g. `sv=...&se=...&sp=a&sig=...`
+    pub fn sign(&self, credential: &SharedKeyCredential) -> Result<String, QueueError> {
+        validate_queue_name(&self.queue_name)?;
+        let permissions = self.permissions.to_string();
+        // without a policy to fill them in, the token itself has to say what it allows and for how long
+        if self.identifier.is_none() {
+            if permissions.is_empty() {
+                return Err(BuildError::Missing("SAS permissions").into());
+            }
+            if self.expiry.is_none() {
+                return Err(BuildError::Missing("SAS expiry").into());
+            }
+        }
+        if let (Some(start), Some(expiry)) = (self.start, self.expiry) {
+            if start >= expiry {
+                return Err(BuildError::OutOfRange("SAS start has to be before its expiry".to_string()).into());
+            }
+        }
+        let start = self.start.as_ref().map(format_sas_time).unwrap_or_default();
+        let expiry = self.expiry.as_ref().map(format_sas_time).unwrap_or_default();
+        let identifier = self.identifier.clone().unwrap_or_default();
+        let ip_range = self.ip_range.clone().unwrap_or_default();
+        let protocol = self.protocol.to_string();
+        let canonical_resource = format!("/queue/{}/{}", credential.account_name(), self.queue_name);
+
+        let string_to_sign = [
+            permissions.as_str(),
+            &start,
+            &expiry,
+            &canonical_resource,
+            &identifier,
+            &ip_range,
+            &protocol,
+            SAS_VERSION,
+        ]
+        .join("\n");
+        let signature = credential.sign(&string_to_sign)?;
+
+        let mut query = vec![("sv", SAS_VERSION.to_string())];
+        let optional = [
+            ("st", start),
+            ("se", expiry),
+            ("sp", permissions),
+            ("si", identifier),
+            ("sip", ip_range),
+        ];
+        query.extend(optional.into_iter().filter(|(_, value)| !value.is_empty()));
+        query.push(("spr", protocol));
+        query.push(("sig", signature));
+        Ok(encode_query(&query))
+    }
+}
+
+impl QueueClient {
+    /// a service SAS for this queue, signed with the client's account key: `permissions` as letters (`raup`,
+    /// any order), valid from `start` (now if `None`) to `expiry`. Back comes the query string to hand out, to
+    /// go on the end of the queue url or into `SasCredential::new`.
+    /// only works for a client built with an account key. For anything more (ip ranges, stored access
+    /// policies) use `QueueSasBuilder`, which this is a shorthand for.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/create-service-sas
+    pub fn generate_sas(
+        &self,
+        permissions: &str,
+        expiry: DateTime<Utc>,
+        start: Option<DateTime<Utc>>,
+    ) -> Result<String, QueueError> {
+        let credential = self.credential.shared_key().ok_or_else(|| {
+            QueueError::InvalidConfig("generating a SAS needs a client that authorizes with the account key".to_string())
+        })?;
+        let mut builder = QueueSasBuilder::new(&self.queue_name).permissions(permissions.parse()?).expiry(expiry);
+        if let Some(start) = start {
+            builder = builder.start(start);
+        }
+        builder.sign(credential)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the times from the service SAS example in the docs
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/create-service-sas#service-sas-examples
+    fn time(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    // the signatures below aren't the ones on the docs page (that's someone's real account key), they're the
+    // documented string to sign HMAC'd with the well known emulator key, worked out independently of this crate
+    #[test]
+    fn generate_sas_matches_the_documented_queue_example() {
+        let client = QueueClient::emulator("myqueue").unwrap();
+        let start = time("2015-04-29T22:18:26Z");
+        let expiry = time("2015-04-30T02:23:26Z");
+
+        // string to sign:
+        // ra\n2015-04-29T22:18:26Z\n2015-04-30T02:23:26Z\n/queue/devstoreaccount1/myqueue\n\n\nhttps\n2021-12-02
+        let sas = client.generate_sas("ar", expiry, Some(start)).unwrap();
+        assert_eq!(
+            sas,
+            "sv=2021-12-02&st=2015-04-29T22%3A18%3A26Z&se=2015-04-30T02%3A23%3A26Z&sp=ra&spr=https\
+             &sig=t5X1GLTMKKJvMKJviTzXxEPmayWnhmtIE1AGZEFJpoQ%3D"
+        );
+
+        assert!(client.generate_sas("rx", expiry, None).is_err());
+        let sas_client = crate::QueueClientBuilder::new()
+            .account_name("devstoreaccount1")
+            .endpoint("http://127.0.0.1:10001/devstoreaccount1")
+            .queue_name("myqueue")
+            .sas_token(&sas)
+            .build()
+            .unwrap();
+        assert!(sas_client.generate_sas("r", expiry, None).is_err(), "a SAS client has no key to sign with");
+    }
+
+    fn emulator_key() -> SharedKeyCredential {
+        SharedKeyCredential::new("devstoreaccount1", crate::connection_string::EMULATOR_ACCOUNT_KEY).unwrap()
+    }
+
+    #[test]
+    fn queue_sas_signatures() {
+        let credential = emulator_key();
+        let permissions = QueueSasPermissions { read: true, add: true, ..Default::default() };
+        let sas = QueueSasBuilder::new("myqueue")
+            .permissions(permissions)
+            .start(time("2015-04-29T22:18:26Z"))
+            .expiry(time("2015-04-30T02:23:26Z"))
+            .ip_range("168.1.5.60-168.1.5.70")
+            .sign(&credential)
+            .unwrap();
+        assert_eq!(
+            sas,
+            "sv=2021-12-02&st=2015-04-29T22%3A18%3A26Z&se=2015-04-30T02%3A23%3A26Z&sp=ra&sip=168.1.5.60-168.1.5.70\
+             &spr=https&sig=14qloj8Oc4k72bvVPMXCfZ8KkGq6ueGYecXdwpCrVtk%3D"
+        );
+
+        // everything from a stored access policy, only its id in the token
+        let sas = QueueSasBuilder::new("orders")
+            .identifier("partner-add")
+            .protocol(SasProtocol::HttpsAndHttp)
+            .sign(&credential)
+            .unwrap();
+        assert_eq!(
+            sas,
+            "sv=2021-12-02&si=partner-add&spr=https%2Chttp&sig=MTKWY%2BBIXkjlpVgEw0yCWVywmiyzyiKV5hKE9tTox6I%3D"
+        );
+
+        // no policy, so permissions and expiry have to be there
+        assert!(QueueSasBuilder::new("orders").expiry(time("2015-04-30T02:23:26Z")).sign(&credential).is_err());
+        assert!(QueueSasBuilder::new("orders").permissions(permissions).sign(&credential).is_err());
+        assert_eq!("pua".parse::<QueueSasPermissions>().unwrap().to_string(), "aup");
+    }
+
+    #[test]
+    fn account_sas_signatures() {
+        let credential = emulator_key();
+        // the account SAS example in the docs: blob and file services, service level, read and write
+        // https://learn.microsoft.com/en-us/rest/api/storageservices/create-account-sas#account-sas-example
+        let sas = AccountSasBuilder::new(time("2015-04-30T02:23:26Z"))
+            .services(AccountSasServices { blob: true, file: true, ..Default::default() })
+            .resource_types(AccountSasResourceTypes { service: true, ..Default::default() })
+            .permissions(AccountSasPermissions { read: true, write: true, ..Default::default() })
+            .start(time("2015-04-29T22:18:26Z"))
+            .ip_range("168.1.42.1-168.1.42.255")
+            .sign(&credential)
+            .unwrap();
+        assert_eq!(
+            sas,
+            "sv=2021-12-02&ss=bf&srt=s&sp=rw&st=2015-04-29T22%3A18%3A26Z&se=2015-04-30T02%3A23%3A26Z\
+             &sip=168.1.42.1-168.1.42.255&spr=https&sig=Oa7z6%2Bdc8bFUCVcw3MOWKItZFE2fs2XXAh%2FPHwgjrKI%3D"
+        );
+
+        // queue only, send and process messages
+        let sas = AccountSasBuilder::new(time("2015-04-30T02:23:26Z"))
+            .resource_types(AccountSasResourceTypes { container: true, object: true, ..Default::default() })
+            .permissions(AccountSasPermissions { add: true, process: true, ..Default::default() })
+            .sign(&credential)
+            .unwrap();
+        assert_eq!(
+            sas,
+            "sv=2021-12-02&ss=q&srt=co&sp=ap&se=2015-04-30T02%3A23%3A26Z&spr=https\
+             &sig=YaSOcFMvtHhmHrSSNjaYiGJwCbgFbt3C4OuT%2BKegbPo%3D"
+        );
+    }
+}
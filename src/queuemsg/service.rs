@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::stream::{self, Stream, TryStreamExt};
+
+use crate::builder::{
+    check_invalid_certs, check_plain_http, validate_api_version, validate_endpoint, validate_options,
+    validate_queue_name,
+};
+use crate::{
+    connection_string, xml, ClientOptions, CloudEnvironment, Credential, QueueClient, QueueError,
+    SharedKeyCredential, DEFAULT_API_VERSION,
+};
+
+/// the most queues azure lists in one go, and what it uses when `max_results` isn't set
+pub const MAX_LIST_RESULTS: u32 = 5000;
+
+/// which queues `list_queues` asks for. `Default` is every queue, names only, a page at a time.
+/// `let mut options = ListQueuesOptions::default(); options.prefix = Some("tenant-".to_string());`
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct ListQueuesOptions {
+    /// only queues whose names start with this
+    pub prefix: Option<String>,
+    /// where to carry on from, the `next_marker` of the page before
+    pub marker: Option<String>,
+    /// at most this many queues in the page, 1-5000. Azure can send fewer and still have more to come.
+    pub max_results: Option<u32>,
+    /// fill in each queue's user metadata, it's left empty otherwise
+    pub include_metadata: bool,
+}
+
+/// one queue from a listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueItem {
+    pub name: String,
+    /// names as azure has them (lowercased), only with `include_metadata`
+    pub metadata: HashMap<String, String>,
+}
+
+/// one page of `list_queues`. When `next_marker` is `Some` there's more, put it in `ListQueuesOptions::marker`
+/// for the next page. `None` means that was the last of them.
+#[derive(Debug, Clone)]
+pub struct QueuesPage {
+    pub queues: Vec<QueueItem>,
+    pub next_marker: Option<String>,
+}
+
+/// a client for a whole storage account rather than one queue. It owns the credentials and one http connection
+/// pool, and hands out `QueueClient`s for individual queues that share both.
+/// account level operations (listing queues, service properties) belong on here rather than `QueueClient`.
+#[derive(Clone, Debug)]
+pub struct QueueServiceClient {
+    account_name: String,
+    credential: Arc<dyn Credential>,
+    queue_endpoint: String,
+    api_version: String,
+    options: ClientOptions,
+    http: reqwest::Client,
+    /// see `QueueClient::own_transport`, the queue clients handed out get it too
+    own_transport: bool,
+    /// see `allow_insecure_http`
+    allow_insecure_http: bool,
+}
+
+impl QueueServiceClient {
+    /// an account in the public azure cloud. `account_key` is the base64 key from the portal, a key that
+    /// doesn't decode is a `QueueError::InvalidAccountKey`.
+    pub fn new(account_name: &str, account_key: &str) -> Result<QueueServiceClient, QueueError> {
+        let queue_endpoint = CloudEnvironment::Public.queue_endpoint(account_name);
+        QueueServiceClient::from_parts(account_name, account_key, &queue_endpoint)
+    }
+
+    /// same connection string rules as `QueueClient::from_connection_string`, just without a queue name.
+    pub fn from_connection_string(conn_str: &str) -> Result<QueueServiceClient, QueueError> {
+        let parsed = connection_string::parse_connection_string(conn_str)?;
+        QueueServiceClient::from_parts(&parsed.account_name, &parsed.account_key, &parsed.queue_endpoint)
+    }
+
+    fn from_parts(account_name: &str, account_key: &str, queue_endpoint: &str) -> Result<QueueServiceClient, QueueError> {
+        Ok(QueueServiceClient {
+            account_name: account_name.to_string(),
+            credential: Arc::new(SharedKeyCredential::new(account_name, account_key)?),
+            queue_endpoint: queue_endpoint.to_string(),
+            api_version: DEFAULT_API_VERSION.to_string(),
+            options: ClientOptions::default(),
+            http: ClientOptions::default().http_client()?,
+            own_transport: false,
+            allow_insecure_http: false,
+        })
+    }
+
+    /// timeouts, retries and user agent for this client and every queue client it hands out afterwards.
+    /// this replaces the connection pool, so call it before `queue_client`. Options the builder would refuse (a
+    /// zero timeout, a server timeout over 30 seconds) and an http client reqwest can't build (usually the tls
+    /// backend) are a `QueueError::InvalidConfig`.
+    pub fn with_options(mut self, options: ClientOptions) -> Result<QueueServiceClient, QueueError> {
+        validate_options(&options)?;
+        check_invalid_certs(&self.account_name, &self.queue_endpoint, options.danger_accept_invalid_certs)?;
+        self.http = options.http_client()?;
+        self.own_transport = options.has_transport_settings();
+        self.options = options;
+        Ok(self)
+    }
+
+    /// let `with_endpoint` take plain `http://` somewhere other than this machine, the same as
+    /// `QueueClientBuilder::allow_insecure_http`. Call it before `with_endpoint`.
+    pub fn allow_insecure_http(mut self, allow: bool) -> QueueServiceClient {
+        self.allow_insecure_http = allow;
+        self
+    }
+
+    /// use an explicit base url instead of the one worked out from the account name, e.g. a private endpoint
+    /// or a proxy on another port. Same rules as `QueueClientBuilder::endpoint` (plain http only to loopback
+    /// unless `allow_insecure_http`), queue clients handed out afterwards use it too.
+    pub fn with_endpoint(mut self, endpoint: &str) -> Result<QueueServiceClient, QueueError> {
+        let endpoint = validate_endpoint(endpoint)?;
+        check_plain_http(&endpoint, self.allow_insecure_http)?;
+        check_invalid_certs(&self.account_name, &endpoint, self.options.danger_accept_invalid_certs)?;
+        self.queue_endpoint = endpoint;
+        Ok(self)
+    }
+
+    /// the x-ms-version for this client and every queue client it hands out afterwards.
+    /// defaults to `DEFAULT_API_VERSION`. Something that doesn't look like a version is a `QueueError::InvalidConfig`.
+    pub fn with_api_version(mut self, api_version: &str) -> Result<QueueServiceClient, QueueError> {
+        validate_api_version(api_version, self.credential.min_api_version())?;
+        self.api_version = api_version.to_string();
+        Ok(self)
+    }
+
+    /// use your own reqwest client (and its connection pool) for this client and every queue client it hands
+    /// out afterwards. Its settings win over `ClientOptions`' timeouts and user agent.
+    pub fn with_http_client(mut self, http_client: reqwest::Client) -> QueueServiceClient {
+        self.http = http_client;
+        self.own_transport = true;
+        self
+    }
+
+    pub fn account_name(&self) -> &str {
+        &self.account_name
+    }
+
+    /// base url of the queue service for the account, without any queue name.
+    pub fn queue_endpoint(&self) -> &str {
+        &self.queue_endpoint
+    }
+
+    /// one page of the queues in the account, see `ListQueuesOptions` and `QueuesPage`.
+    /// https://learn.microsoft.com/en-us/rest/api/storageservices/list-queues1
+    pub async fn list_queues(&self, options: &ListQueuesOptions) -> Result<QueuesPage, QueueError> {
+        let mut query = vec![("comp", "list".to_string())];
+        if let Some(prefix) = &options.prefix {
+            query.push(("prefix", prefix.clone()));
+        }
+        if let Some(marker) = options.marker.as_ref().filter(|marker| !marker.is_empty()) {
+            query.push(("marker", marker.clone()));
+        }
+        if let Some(max_results) = options.max_results {
+            if max_results == 0 || max_results > MAX_LIST_RESULTS {
+                return Err(QueueError::InvalidConfig(format!(
+                    "max results must be between 1 and {}, got {}",
+                    MAX_LIST_RESULTS, max_results
+                )));
+            }
+            query.push(("maxresults", max_results.to_string()));
+        }
+        if options.include_metadata {
+            query.push(("include", "metadata".to_string()));
+        }
+        let response = self.account_client().execute(reqwest::Method::GET, "", &query, None).await?;
+        parse_queues_page(response.text()?)
+    }
+
+    /// every queue `list_queues` would find, fetching the next page only once the stream has handed out the
+    /// last one, so stopping early doesn't cost the rest of the listing. `options.max_results` is the page size
+    /// and `options.marker` where to start. An error fetching a page comes out of the stream and ends it.
+    /// nothing runs in the background, drop the stream and that's it.
+    pub fn list_queues_stream(
+        &self,
+        options: ListQueuesOptions,
+    ) -> impl Stream<Item = Result<QueueItem, QueueError>> + Send + 'static {
+        let client = self.clone();
+        stream::try_unfold(Some(options), move |options| {
+            let client = client.clone();
+            async move {
+                let mut options = match options {
+                    Some(options) => options,
+                    None => return Ok::<_, QueueError>(None),
+                };
+                let page = client.list_queues(&options).await?;
+                let next = page.next_marker.map(|marker| {
+                    options.marker = Some(marker);
+                    options
+                });
+                Ok(Some((stream::iter(page.queues.into_iter().map(Ok::<_, QueueError>)), next)))
+            }
+        })
+        .try_flatten()
+    }
+
+    /// the account as a `QueueClient` with no queue name, so account level requests get the same signing and
+    /// retries as everything else. The url comes out as the endpoint with a `/` on the end and the
+    /// canonicalized resource as `/{account}/`, which is what azure wants for those.
+    fn account_client(&self) -> QueueClient {
+        self.client_for("")
+    }
+
+    /// a client for one queue in this account. It's cheap - the connection pool is shared, not copied.
+    /// a name azure won't accept is a `QueueError::InvalidConfig` here, same as from the builder, rather than
+    /// a 400 on the first request.
+    pub fn queue_client(&self, queue_name: &str) -> Result<QueueClient, QueueError> {
+        validate_queue_name(queue_name)?;
+        Ok(self.client_for(queue_name))
+    }
+
+    fn client_for(&self, queue_name: &str) -> QueueClient {
+        let mut client = QueueClient::from_parts(
+            &self.account_name,
+            self.credential.clone(),
+            queue_name,
+            &self.queue_endpoint,
+            self.http.clone(),
+        );
+        client.api_version = self.api_version.clone();
+        client.own_transport = self.own_transport;
+        client.options = self.options.clone();
+        client
+    }
+}
+
+/// ```text
+/// <EnumerationResults ServiceEndpoint="https://myaccount.queue.core.windows.net/">
+///   <Prefix>string-value</Prefix>
+///   <Marker>string-value</Marker>
+///   <MaxResults>int-value</MaxResults>
+///   <Queues>
+///     <Queue>
+///       <Name>string-value</Name>
+///       <Metadata>
+///         <metadata-name>value</metadata-name>
+///       </Metadata>
+///     </Queue>
+///   </Queues>
+///   <NextMarker />
+/// </EnumerationResults>
+/// ```
+/// the last page has an empty `<NextMarker />`.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/list-queues1#response-body
+fn parse_queues_page(body: &str) -> Result<QueuesPage, QueueError> {
+    let mut queues = Vec::<QueueItem>::new();
+    for queue in xml::elements(body, "Queue") {
+        let name = xml::element(queue, "Name")
+            .map(xml::unescape)
+            .ok_or_else(|| QueueError::InvalidResponse("Queue is missing Name".to_string()))?;
+        let metadata = xml::element(queue, "Metadata")
+            .map(xml::children)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), xml::unescape(value)))
+            .collect();
+        queues.push(QueueItem { name, metadata });
+    }
+    // after the queues, so metadata that happens to be called NextMarker can't be mistaken for it
+    let after_queues = body.rfind("</Queues>").map(|end| &body[end..]).unwrap_or(body);
+    let next_marker = xml::element(after_queues, "NextMarker")
+        .map(xml::unescape)
+        .filter(|marker| !marker.is_empty());
+    Ok(QueuesPage { queues, next_marker })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection_string::EMULATOR_ACCOUNT_KEY;
+    use crate::tests::{all_requests, request_header, serve, server_signature, Reply};
+
+    /// an `EnumerationResults` page with `queues` as they'd be in the xml (so already escaped) and the raw
+    /// NextMarker, empty on the last page
+    fn queues_page(queues: &[&str], next_marker: &str) -> String {
+        let queues: Vec<String> = queues.iter().map(|queue| format!("<Queue>{}</Queue>", queue)).collect();
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?><EnumerationResults \
+             ServiceEndpoint=\"https://myaccount.queue.core.windows.net/\"><Prefix>tenant-</Prefix>\
+             <MaxResults>2</MaxResults><Queues>{}</Queues><NextMarker>{}</NextMarker></EnumerationResults>",
+            queues.join(""),
+            next_marker
+        )
+    }
+
+    #[test]
+    fn queue_pages_are_parsed_and_unescaped() {
+        let first = queues_page(
+            &[
+                "<Name>tenant-a</Name><Metadata><owner>billing &amp; ops</owner><tier /></Metadata>",
+                "<Name>tenant-&lt;b&gt;</Name>",
+            ],
+            "/myaccount/tenant-&lt;c&gt;",
+        );
+        let page = parse_queues_page(&first).unwrap();
+        let names: Vec<&str> = page.queues.iter().map(|queue| queue.name.as_str()).collect();
+        assert_eq!(names, vec!["tenant-a", "tenant-<b>"]);
+        let metadata = HashMap::from([
+            ("owner".to_string(), "billing & ops".to_string()),
+            ("tier".to_string(), String::new()),
+        ]);
+        assert_eq!(page.queues[0].metadata, metadata);
+        assert!(page.queues[1].metadata.is_empty());
+        assert_eq!(page.next_marker.as_deref(), Some("/myaccount/tenant-<c>"));
+
+        // the last page, with metadata that happens to be called NextMarker
+        let last = queues_page(&["<Name>tenant-&lt;c&gt;</Name><Metadata><NextMarker>x</NextMarker></Metadata>"], "");
+        let page = parse_queues_page(&last).unwrap();
+        assert_eq!(page.queues[0].name, "tenant-<c>");
+        assert_eq!(page.queues[0].metadata["NextMarker"], "x");
+        assert_eq!(page.next_marker, None);
+        let last = last.replace("<NextMarker></NextMarker>", "<NextMarker />");
+        assert_eq!(parse_queues_page(&last).unwrap().next_marker, None);
+
+        let empty = parse_queues_page(&queues_page(&[], "")).unwrap();
+        assert!(empty.queues.is_empty() && empty.next_marker.is_none());
+    }
+
+    /// a service client for the emulator account, pointed at `serve`
+    fn service_client(endpoint: &str) -> QueueServiceClient {
+        let client = QueueServiceClient::from_connection_string("UseDevelopmentStorage=true").unwrap();
+        client.with_endpoint(endpoint).unwrap()
+    }
+
+    fn ok(body: String) -> Reply {
+        ("200 OK", String::new(), body)
+    }
+
+    #[tokio::test]
+    async fn list_queues_follows_the_marker_from_page_to_page() {
+        let pages = vec![
+            ok(queues_page(&["<Name>tenant-a</Name>", "<Name>tenant-b</Name>"], "/devstoreaccount1/tenant-c")),
+            ok(queues_page(&["<Name>tenant-c</Name>"], "")),
+        ];
+        let (endpoint, requests) = serve(pages).await;
+        let client = service_client(&endpoint);
+        let mut options = ListQueuesOptions {
+            prefix: Some("tenant-".to_string()),
+            max_results: Some(2),
+            ..ListQueuesOptions::default()
+        };
+
+        let first = client.list_queues(&options).await.unwrap();
+        assert_eq!(first.queues.len(), 2);
+        options.marker = first.next_marker;
+        let second = client.list_queues(&options).await.unwrap();
+        assert_eq!(second.queues[0].name, "tenant-c");
+        assert_eq!(second.next_marker, None);
+
+        let requests = all_requests(requests).await;
+        assert!(requests[0].starts_with("GET /devstoreaccount1/?comp=list&prefix=tenant-&maxresults=2 "));
+        assert!(requests[1].starts_with(
+            "GET /devstoreaccount1/?comp=list&prefix=tenant-&marker=%2Fdevstoreaccount1%2Ftenant-c&maxresults=2 "
+        ));
+        // the account level resource, with the marker decoded
+        let resource = "/devstoreaccount1/devstoreaccount1/\ncomp:list\nmarker:/devstoreaccount1/tenant-c\n\
+                        maxresults:2\nprefix:tenant-";
+        let signature = server_signature(&requests[1], EMULATOR_ACCOUNT_KEY, resource);
+        let authorization = request_header(&requests[1], "authorization").unwrap();
+        assert_eq!(authorization, format!("SharedKey devstoreaccount1:{}", signature));
+    }
+
+    #[tokio::test]
+    async fn queue_stream_fetches_each_page_only_when_it_gets_there() {
+        let pages = vec![
+            ok(queues_page(&["<Name>q1</Name>", "<Name>q2</Name>"], "page-2")),
+            ok(queues_page(&["<Name>q3</Name>", "<Name>q4</Name>"], "page-3")),
+            ok(queues_page(&["<Name>q5</Name>"], "")),
+        ];
+        let (endpoint, mut requests) = serve(pages).await;
+        let client = service_client(&endpoint);
+        let options = ListQueuesOptions { max_results: Some(2), ..ListQueuesOptions::default() };
+        let stream = client.list_queues_stream(options);
+        futures::pin_mut!(stream);
+        let name = |queue: Option<QueueItem>| queue.map(|queue| queue.name);
+        // how many pages have been asked for so far
+        let mut fetched = 0;
+        let mut pages_fetched = || {
+            while let Ok(request) = requests.try_recv() {
+                assert!(request.contains("maxresults=2"));
+                fetched += 1;
+            }
+            fetched
+        };
+
+        assert_eq!(pages_fetched(), 0, "nothing is fetched until the stream is polled");
+        assert_eq!(name(stream.try_next().await.unwrap()).as_deref(), Some("q1"));
+        assert_eq!(name(stream.try_next().await.unwrap()).as_deref(), Some("q2"));
+        assert_eq!(pages_fetched(), 1);
+        assert_eq!(name(stream.try_next().await.unwrap()).as_deref(), Some("q3"));
+        assert_eq!(name(stream.try_next().await.unwrap()).as_deref(), Some("q4"));
+        assert_eq!(pages_fetched(), 2, "page 3 was fetched before anything asked for it");
+        assert_eq!(name(stream.try_next().await.unwrap()).as_deref(), Some("q5"));
+        assert_eq!(pages_fetched(), 3);
+        assert_eq!(name(stream.try_next().await.unwrap()), None);
+        assert_eq!(pages_fetched(), 3);
+    }
+}
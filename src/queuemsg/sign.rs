@@ -0,0 +1,84 @@
+use chrono::Utc;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::{
+    canonical_query, format_date_str, ms_header_map, QueueError, RequestParts, SharedKeyCredential,
+    DEFAULT_API_VERSION,
+};
+
+/// sign a request you're building yourself, for a storage operation this crate doesn't do, with the same
+/// code the client signs its own requests with. Works for any storage url on the credential's account, the
+/// canonicalized resource comes from `url` (path and query) rather than from a queue client.
+///
+/// `headers` is everything you're going to send apart from Content-Length (that's `content_length`, in bytes)
+/// and the ones this returns. It gives back the headers to add:
+/// - `x-ms-date`, now
+/// - `x-ms-version`, `DEFAULT_API_VERSION`, unless `headers` already has one in which case that's what's signed
+/// - `Authorization`
+///
+/// you have to send exactly `headers` plus these, with the same values. Add or change an x-ms- header, or
+/// Content-Type/Content-MD5/If-Match and friends, after signing and azure answers 403 AuthenticationFailed.
+/// the date is only good for 15 minutes, so sign just before sending and again for a retry.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key
+pub fn sign_request(
+    credential: &SharedKeyCredential,
+    method: &reqwest::Method,
+    url: &reqwest::Url,
+    headers: &HeaderMap,
+    content_length: usize,
+) -> Result<HeaderMap, QueueError> {
+    let mut pairs = Vec::<(String, String)>::new();
+    for (name, value) in headers {
+        let value = value.to_str().map_err(|_| {
+            QueueError::InvalidConfig(format!("header {} isn't visible ascii, it can't be signed", name))
+        })?;
+        pairs.push((name.as_str().to_string(), value.to_string()));
+    }
+    if headers.contains_key("x-ms-date") {
+        return Err(QueueError::InvalidConfig(
+            "leave x-ms-date out of the headers, sign_request adds it".to_string(),
+        ));
+    }
+
+    let date = format_date_str(Utc::now());
+    let mut added = vec![("x-ms-date".to_string(), date.clone())];
+    if !headers.contains_key("x-ms-version") {
+        added.push(("x-ms-version".to_string(), DEFAULT_API_VERSION.to_string()));
+    }
+    pairs.extend(added.iter().cloned());
+
+    // "/" + account + the url path as it's sent, so path style (azurite) urls have the account name twice
+    let resource_path = format!("/{}{}", credential.account_name(), url.path());
+    let query: Vec<(String, String)> = url.query_pairs().map(|(k, v)| (k.into_owned(), v.into_owned())).collect();
+    let query_refs: Vec<(&str, String)> = query.iter().map(|(name, value)| (name.as_str(), value.clone())).collect();
+    let ms_headers: Vec<(String, String)> = ms_header_map(&pairs).into_iter().collect();
+    let api_version = ms_headers
+        .iter()
+        .find(|(name, _)| name == "x-ms-version")
+        .map(|(_, value)| value.clone())
+        .unwrap_or_default();
+    let parts = RequestParts {
+        method: method.as_str(),
+        account_name: credential.account_name(),
+        content_length,
+        date: &date,
+        date_header: false,
+        api_version: &api_version,
+        canonical_resource: format!("{}{}", resource_path, canonical_query(&query_refs)),
+        resource_path,
+        query,
+        ms_headers,
+        headers: pairs,
+    };
+    let authorization = credential.authorization(&parts)?;
+
+    let mut signed = HeaderMap::new();
+    for (name, value) in added.into_iter().chain(authorization.headers) {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| QueueError::InvalidConfig(format!("bad header name {}: {}", name, e)))?;
+        let value = HeaderValue::from_str(&value)
+            .map_err(|e| QueueError::InvalidConfig(format!("bad value for header {}: {}", name, e)))?;
+        signed.insert(name, value);
+    }
+    Ok(signed)
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::QueueError;
+
+/// the AAD resource (audience) for storage data plane tokens.
+pub(crate) const STORAGE_RESOURCE: &str = "https://storage.azure.com/";
+
+/// the same thing as a v2 endpoint scope.
+pub(crate) const STORAGE_SCOPE: &str = "https://storage.azure.com/.default";
+
+/// where AAD tokens come from unless `AZURE_AUTHORITY_HOST` says otherwise (sovereign clouds).
+pub(crate) const DEFAULT_AUTHORITY_HOST: &str = "https://login.microsoftonline.com";
+
+/// bearer auth needs at least this x-ms-version, anything older only understands SharedKey.
+/// https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-azure-active-directory#call-storage-operations-with-oauth-tokens
+pub const OAUTH_MIN_API_VERSION: &str = "2017-11-09";
+
+/// how long before a token actually expires we stop trusting it and go get another one.
+const REFRESH_MARGIN_MINUTES: i64 = 5;
+
+/// an AAD access token and when it stops working.
+#[derive(Clone)]
+pub struct AccessToken {
+    pub token: String,
+    pub expires_on: DateTime<Utc>,
+}
+
+// tokens are as good as a password, so keep them out of logs.
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessToken")
+            .field("token", &"<redacted>")
+            .field("expires_on", &self.expires_on)
+            .finish()
+    }
+}
+
+impl AccessToken {
+    /// true if the token has expired or will within `margin`.
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        self.expires_on - margin <= Utc::now()
+    }
+}
+
+/// something that can hand out AAD bearer tokens for storage. When a client is given one of these it sends
+/// `Authorization: Bearer <token>` instead of signing requests with the account key.
+/// implementations should do their own caching, `get_token` is called for every request.
+/// the future is boxed so this can live behind an `Arc<dyn TokenCredential>` in the client.
+pub trait TokenCredential: Send + Sync + fmt::Debug {
+    fn get_token(&self) -> BoxFuture<'_, Result<AccessToken, QueueError>>;
+
+    /// what to call this credential in errors, e.g. which one in a `ChainedCredential` failed and why.
+    /// the type name without its module path unless it says otherwise, `ManagedIdentityCredential`.
+    fn name(&self) -> &str {
+        let type_name = std::any::type_name::<Self>();
+        // the generics can have paths in them too, so only look for the last `::` before any `<`
+        let end = type_name.find('<').unwrap_or(type_name.len());
+        let start = type_name[..end].rfind("::").map_or(0, |index| index + 2);
+        &type_name[start..]
+    }
+}
+
+/// holds on to the last token handed out and only calls `refresh` when it's about to expire.
+/// the lock is held across the refresh, so a burst of concurrent sends with a stale token all wait on
+/// one refresh rather than each going off to get their own.
+#[derive(Default)]
+pub(crate) struct TokenCache {
+    token: Mutex<Option<AccessToken>>,
+}
+
+impl TokenCache {
+    pub(crate) async fn get_or_refresh<F, Fut>(&self, refresh: F) -> Result<AccessToken, QueueError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<AccessToken, QueueError>>,
+    {
+        let mut cached = self.token.lock().await;
+        if let Some(token) = cached.as_ref() {
+            if !token.expires_within(Duration::minutes(REFRESH_MARGIN_MINUTES)) {
+                return Ok(token.clone());
+            }
+        }
+        let token = refresh().await?;
+        *cached = Some(token.clone());
+        Ok(token)
+    }
+}
+
+impl fmt::Debug for TokenCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenCache").finish_non_exhaustive()
+    }
+}
+
+#[derive(Deserialize)]
+struct AadToken {
+    access_token: String,
+    expires_in: i64,
+}
+
+/// POST `form` to the tenant's v2 token endpoint and turn the answer into an `AccessToken`. Shared by
+/// everything that talks to AAD directly (client secret, workload identity) rather than through IMDS or the cli.
+/// https://learn.microsoft.com/en-us/entra/identity-platform/v2-oauth2-client-creds-grant-flow
+pub(crate) async fn request_aad_token(
+    http: &reqwest::Client,
+    authority_host: &str,
+    tenant_id: &str,
+    form: &[(&str, &str)],
+) -> Result<AccessToken, QueueError> {
+    let url = format!("{}/{}/oauth2/v2.0/token", authority_host.trim_end_matches('/'), tenant_id);
+    let response = http
+        .post(url)
+        .form(form)
+        .send()
+        .await
+        .map_err(|e| QueueError::Credential(format!("couldn't reach AAD token endpoint: {}", e)))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(QueueError::Credential(format!("AAD token request failed with {}: {}", status, body)));
+    }
+    let token: AadToken = response
+        .json()
+        .await
+        .map_err(|e| QueueError::Credential(format!("unexpected AAD token response: {}", e)))?;
+    Ok(AccessToken {
+        token: token.access_token,
+        expires_on: Utc::now() + Duration::seconds(token.expires_in),
+    })
+}
@@ -0,0 +1,41 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{QueueClient, QueueError, QueueMessage, SentMessage};
+
+impl QueueClient {
+    /// `get_messages`, with each message's text parsed as json into a `T`. The text is decoded first with the
+    /// client's message encoding, so base64 (the default) is fine. Each `T` comes paired with its message, which
+    /// is what `delete_message` needs once you're done with it.
+    /// one body that doesn't parse is a `QueueError::Deserialize` with that message in it, to dead-letter or
+    /// delete. The rest of the batch stays hidden until `visibility` runs out and then comes back, so nothing is
+    /// lost, it's just later.
+    pub async fn get_typed<T: DeserializeOwned>(
+        &self,
+        num: u32,
+        visibility: u32,
+    ) -> Result<Vec<(QueueMessage, T)>, QueueError> {
+        let messages = self.get_messages(num, Some(visibility)).await?;
+        let mut typed = Vec::<(QueueMessage, T)>::with_capacity(messages.len());
+        for message in messages {
+            match serde_json::from_str::<T>(&message.message_text) {
+                Ok(value) => typed.push((message, value)),
+                Err(error) => {
+                    return Err(QueueError::Deserialize {
+                        message: Box::new(message),
+                        error,
+                    })
+                }
+            }
+        }
+        Ok(typed)
+    }
+
+    /// `send_message` with `value` as json, which `get_typed` reads back. It goes through the client's message
+    /// encoding and size check like any other message. A value serde_json can't serialize is
+    /// `QueueError::Serialize` and nothing is sent.
+    pub async fn put_typed<T: Serialize>(&self, value: &T) -> Result<SentMessage, QueueError> {
+        let message_text = serde_json::to_string(value).map_err(QueueError::Serialize)?;
+        self.send_message(message_text).await
+    }
+}
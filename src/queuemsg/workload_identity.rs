@@ -0,0 +1,51 @@
+use futures::future::BoxFuture;
+
+use crate::token::{request_aad_token, AccessToken, TokenCache, TokenCredential, DEFAULT_AUTHORITY_HOST, STORAGE_SCOPE};
+use crate::{optional_env, required_env, QueueError};
+
+/// what AAD calls a signed jwt handed over in place of a client secret.
+const JWT_BEARER_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+/// AKS workload identity: the pod gets a projected service account token and `AZURE_CLIENT_ID`,
+/// `AZURE_TENANT_ID` and `AZURE_FEDERATED_TOKEN_FILE` (plus `AZURE_AUTHORITY_HOST`) from the webhook, and that
+/// token is swapped at AAD for a storage one via a federated credential on the app registration / identity.
+/// the token file is read again on every refresh, kubelet rotates it and the old one stops working.
+/// like `EnvironmentCredential` the variables are read when a token is needed, so off aks this is an error
+/// from `get_token` that `ChainedCredential` moves past.
+/// https://learn.microsoft.com/en-us/azure/aks/workload-identity-overview
+#[derive(Debug, Default)]
+pub struct WorkloadIdentityCredential {
+    http: reqwest::Client,
+    cache: TokenCache,
+}
+
+impl WorkloadIdentityCredential {
+    pub fn new() -> WorkloadIdentityCredential {
+        WorkloadIdentityCredential::default()
+    }
+
+    async fn request_token(&self) -> Result<AccessToken, QueueError> {
+        let tenant_id = required_env("AZURE_TENANT_ID")?;
+        let client_id = required_env("AZURE_CLIENT_ID")?;
+        let token_file = required_env("AZURE_FEDERATED_TOKEN_FILE")?;
+        let authority_host =
+            optional_env("AZURE_AUTHORITY_HOST")?.unwrap_or_else(|| DEFAULT_AUTHORITY_HOST.to_string());
+        let assertion = tokio::fs::read_to_string(&token_file).await.map_err(|e| {
+            QueueError::Credential(format!("couldn't read federated token file {}: {}", token_file, e))
+        })?;
+        let form = [
+            ("grant_type", "client_credentials"),
+            ("client_id", client_id.as_str()),
+            ("client_assertion_type", JWT_BEARER_ASSERTION_TYPE),
+            ("client_assertion", assertion.trim()),
+            ("scope", STORAGE_SCOPE),
+        ];
+        request_aad_token(&self.http, &authority_host, &tenant_id, &form).await
+    }
+}
+
+impl TokenCredential for WorkloadIdentityCredential {
+    fn get_token(&self) -> BoxFuture<'_, Result<AccessToken, QueueError>> {
+        Box::pin(self.cache.get_or_refresh(|| self.request_token()))
+    }
+}
@@ -0,0 +1,84 @@
+//! the queue service only ever sends back a handful of tiny, flat xml documents, so like `create_content_string`
+//! we do this by hand instead of pulling in an xml crate.
+
+/// return the text between the first `<tag>` and its matching `</tag>`, or `None` if it isn't there.
+/// the text is returned as is - it's still xml escaped.
+pub(crate) fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&close)? + start;
+    Some(&xml[start..end])
+}
+
+/// every `<tag>...</tag>` in the document in order, e.g. each `QueueMessage` in a `QueueMessagesList`.
+pub(crate) fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut found = Vec::<&str>::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let start = start + open.len();
+        match rest[start..].find(&close) {
+            Some(end) => {
+                found.push(&rest[start..start + end]);
+                rest = &rest[start + end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    found
+}
+
+/// escape the five characters xml cares about, so message text with `&` or `<` in it (json, html, whatever)
+/// doesn't break the document we send. `&` has to go first or we'd escape our own escapes.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// undo the five predefined xml entities. `&amp;` has to go last or `&amp;lt;` turns into `<`.
+pub(crate) fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// the `<name>value</name>` children of an element whose tag names aren't known in advance, like the user
+/// metadata in a queue listing, in order. `<name />` is an empty value. Values are still xml escaped, and
+/// anything that isn't a flat list of children stops the scan.
+pub(crate) fn children(xml: &str) -> Vec<(&str, &str)> {
+    let mut found = Vec::<(&str, &str)>::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        let after = &rest[start + 1..];
+        let end = match after.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &after[..end];
+        if let Some(name) = tag.strip_suffix('/') {
+            found.push((name.trim(), ""));
+            rest = &after[end + 1..];
+            continue;
+        }
+        if tag.starts_with('/') {
+            break;
+        }
+        let close = format!("</{}>", tag);
+        let value_start = end + 1;
+        match after[value_start..].find(&close) {
+            Some(value_end) => {
+                found.push((tag, &after[value_start..value_start + value_end]));
+                rest = &after[value_start + value_end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    found
+}